@@ -1,10 +1,6 @@
 use criterion::{black_box, criterion_group, criterion_main, Criterion};
 use dirs::cache_dir;
-use poe_tools::{
-    bundle_fs::{from_cdn, from_steam, FS},
-    bundle_loader::cdn_base_url,
-    steam::steam_folder_search,
-};
+use poe_tools::{bundle_fs::FS, bundle_loader::cdn_base_urls, steam::steam_folder_search};
 
 fn fs_benchmark_steam(c: &mut Criterion) {
     read_some_files("steam", c, steam_fs(), "data/skill*.datc64");
@@ -28,35 +24,35 @@ fn fs_benchmark_cdn_large_art(c: &mut Criterion) {
 
 fn fs_load_index(c: &mut Criterion) {
     let cache_dir = cache_dir().unwrap().join("poe_data_tools");
-    let base_url = cdn_base_url(&cache_dir, "2").expect("Failed to get base url");
+    let base_urls = cdn_base_urls(&cache_dir, "2").expect("Failed to get base urls");
     c.bench_function("load_index", |b| {
         b.iter(|| {
-            let _fs = from_cdn(&base_url, &cache_dir);
+            let _fs = FS::from_cdn(&base_urls, &cache_dir);
         });
     });
 }
 
 fn steam_fs() -> FS {
-    from_steam(steam_folder_search("2").expect("Can't find steam folder"))
+    FS::from_steam(steam_folder_search("2").expect("Can't find steam folder"))
         .expect("Failed to load file system")
 }
 
 fn cdn_fs() -> FS {
     let cache_dir = cache_dir().unwrap().join("poe_data_tools");
-    let base_url = cdn_base_url(&cache_dir, "2").expect("Failed to get base url");
-    from_cdn(&base_url, &cache_dir).expect("Failed to load file system")
+    let base_urls = cdn_base_urls(&cache_dir, "2").expect("Failed to get base urls");
+    FS::from_cdn(&base_urls, &cache_dir).expect("Failed to load file system")
 }
 
-fn read_some_files(source: &str, c: &mut Criterion, mut fs: FS, pattern: &str) {
+fn read_some_files(source: &str, c: &mut Criterion, fs: FS, pattern: &str) {
     let glob = glob::Pattern::new(pattern).unwrap();
 
-    let list = fs.list();
+    let list = fs.list().collect::<Vec<_>>();
     // warm caches
     list.iter().filter(|p| glob.matches(p)).for_each(|p| {
         let _contents = fs.read(p).expect("Failed to read file");
     });
 
-    let mut list = fs.list();
+    let mut list = fs.list().collect::<Vec<_>>();
     c.bench_function(format!("read_files_{}", source).as_str(), |b| {
         b.iter(|| {
             black_box(&mut list)
@@ -0,0 +1,12 @@
+#![no_main]
+
+use bytes::Bytes;
+use libfuzzer_sys::fuzz_target;
+use poe_tools::bundle::Bundle;
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok(bundle) = Bundle::from_bytes(Bytes::copy_from_slice(data)) {
+        let _ = bundle.read_range(0, 1);
+        let _ = bundle.decode_block(0);
+    }
+});
@@ -0,0 +1,9 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use poe_tools::bundle_index::parse_bundle_index;
+
+// Parses the decompressed contents of an index, the compressed wrapper is covered by `bundle`
+fuzz_target!(|data: &[u8]| {
+    let _ = parse_bundle_index(data);
+});
@@ -0,0 +1,23 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use poe_tools::dat::table_view::DatTable;
+
+fuzz_target!(|data: &[u8]| {
+    let Ok(table) = DatTable::from_raw_bytes(data) else {
+        return;
+    };
+
+    // Read every column position with each of the pointer based views
+    for offset in 0..table.width() {
+        if let Ok(strings) = table.view_col_as_string(offset) {
+            strings.for_each(drop);
+        }
+        if let Ok(arrays) = table.view_col_as_array(offset, 4) {
+            arrays.for_each(drop);
+        }
+        if let Ok(arrays) = table.view_col_as_array_of_strings(offset) {
+            arrays.for_each(drop);
+        }
+    }
+});
@@ -0,0 +1,13 @@
+#![no_main]
+
+use bytes::Bytes;
+use libfuzzer_sys::fuzz_target;
+use poe_tools::ggpk::GGPK;
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok(ggpk) = GGPK::from_bytes(Bytes::copy_from_slice(data)) {
+        for path in ggpk.list() {
+            let _ = ggpk.read(&path);
+        }
+    }
+});
@@ -0,0 +1,22 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use poe_tools::{bundle_index::PathRep, path::parse_paths};
+
+fuzz_target!(|data: &[u8]| {
+    // The first 8 bytes place the path rep within the rest of the input
+    if data.len() < 8 {
+        return;
+    }
+    let (header, path_rep_bundle) = data.split_at(8);
+    let path_rep = PathRep {
+        hash: 0,
+        offset: u32::from_le_bytes(header[..4].try_into().unwrap()),
+        size: u32::from_le_bytes(header[4..].try_into().unwrap()),
+        recursive_size: 0,
+    };
+
+    if let Ok(parsed) = parse_paths(path_rep_bundle, &path_rep) {
+        let _ = parsed.get_paths();
+    }
+});
@@ -0,0 +1,173 @@
+use std::collections::{HashMap, VecDeque};
+
+use anyhow::anyhow;
+use rayon::iter::{IntoParallelIterator, ParallelIterator};
+
+use crate::{bundle_fs::FS, bundle_index::FileInfo, file_system::BatchReadItem};
+
+/// Default number of decoded bytes a batch read may hold before handing them out
+pub const DEFAULT_BATCH_READ_LIMIT: usize = 64 * 1024 * 1024;
+
+/// Lazy iterator over the results of [`FS::batch_read`]. Files are decoded a window at a time,
+/// with each window holding at most the configured number of bytes, so memory use stays steady
+/// no matter how many files are requested. Bundles within a window are loaded and decoded in
+/// parallel.
+pub struct BatchRead<'a> {
+    fs: &'a FS,
+    limit: usize,
+    pending: VecDeque<(&'a str, anyhow::Result<&'a FileInfo>)>,
+    ready: VecDeque<BatchReadItem<'a>>,
+}
+
+impl<'a> BatchRead<'a> {
+    /// Set up a batch read over looked up files. Unless `ordered` is set, files are reordered to
+    /// read each bundle front to back, with failed lookups reported first.
+    pub(crate) fn new(
+        fs: &'a FS,
+        limit: usize,
+        files: impl IntoIterator<Item = (&'a str, anyhow::Result<&'a FileInfo>)>,
+        ordered: bool,
+    ) -> Self {
+        let mut files: Vec<_> = files.into_iter().collect();
+
+        // Neighbouring files within a bundle share decoded blocks
+        if !ordered {
+            files.sort_by_key(|(_, file)| {
+                file.as_ref()
+                    .ok()
+                    .map(|file| (file.bundle_index, file.offset))
+            });
+        }
+
+        Self {
+            fs,
+            limit,
+            pending: files.into(),
+            ready: VecDeque::new(),
+        }
+    }
+
+    /// Decode the next window of files, up to the byte limit
+    fn fill(&mut self) {
+        // Always take at least one file, even if it is larger than the limit by itself
+        let mut window_bytes = 0;
+        let count = self
+            .pending
+            .iter()
+            .take_while(|(_, file)| {
+                let size = file.as_ref().map_or(0, |file| file.size as usize);
+                let fits = window_bytes == 0 || window_bytes + size <= self.limit;
+                window_bytes += size;
+                fits
+            })
+            .count();
+        let window: Vec<_> = self.pending.drain(..count).collect();
+
+        // Group the window by bundle so each bundle is only loaded once
+        let mut bundles = HashMap::<u32, Vec<_>>::new();
+        for (i, (path, file)) in window.iter().enumerate() {
+            if let Ok(file) = file {
+                bundles
+                    .entry(file.bundle_index)
+                    .or_default()
+                    .push((i, *path, *file));
+            }
+        }
+
+        let fs = self.fs;
+        let decoded: Vec<_> = bundles
+            .into_par_iter()
+            .flat_map_iter(|(bundle_index, files)| {
+                let bundle = fs.load_bundle(bundle_index);
+                files
+                    .into_par_iter()
+                    .map(|(i, path, file)| {
+                        let contents = match &bundle {
+                            Ok(bundle) => fs
+                                .read_range(
+                                    bundle_index,
+                                    bundle,
+                                    file.offset as usize,
+                                    file.size as usize,
+                                )
+                                .map(|contents| (path, contents))
+                                .map_err(|e| (path, e)),
+                            Err(e) => Err((path, anyhow!("{:?}", e))),
+                        };
+                        (i, contents)
+                    })
+                    .collect::<Vec<_>>()
+            })
+            .collect();
+
+        // Put everything back in window order
+        let mut slots: Vec<_> = window
+            .into_iter()
+            .map(|(path, file)| file.err().map(|e| Err((path, e))))
+            .collect();
+        for (i, contents) in decoded {
+            slots[i] = Some(contents);
+        }
+        self.ready.extend(slots.into_iter().flatten());
+    }
+}
+
+impl<'a> Iterator for BatchRead<'a> {
+    type Item = BatchReadItem<'a>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.ready.is_empty() {
+            self.fill();
+        }
+        self.ready.pop_front()
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let len = self.pending.len() + self.ready.len();
+        (len, Some(len))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::test_support::TestBundles;
+
+    #[test]
+    fn read_in_small_windows() {
+        let file_system = TestBundles::new()
+            .bundle("a", b"aaaabbbb", &[("a/1.txt", 0, 4), ("a/2.txt", 4, 4)])
+            .bundle("b", b"ccccdddd", &[("b/1.txt", 0, 4), ("b/2.txt", 4, 4)])
+            .fs()
+            .with_batch_read_limit(4);
+        let paths = ["b/2.txt", "missing.txt", "a/2.txt", "b/1.txt", "a/1.txt"];
+        let results: Vec<_> = file_system.batch_read(&paths).collect();
+
+        assert_eq!(results.len(), 5);
+        assert!(matches!(results[0], Err(("missing.txt", _))));
+        let contents: Vec<_> = results[1..]
+            .iter()
+            .map(|r| {
+                let (path, contents) = r.as_ref().unwrap();
+                (*path, contents.as_ref())
+            })
+            .collect();
+        assert_eq!(
+            contents,
+            [
+                ("a/1.txt", &b"aaaa"[..]),
+                ("a/2.txt", &b"bbbb"[..]),
+                ("b/1.txt", &b"cccc"[..]),
+                ("b/2.txt", &b"dddd"[..]),
+            ]
+        );
+
+        let ordered: Vec<_> = file_system
+            .batch_read_ordered(&paths)
+            .map(|r| match r {
+                Ok((path, _)) => path,
+                Err((path, _)) => path,
+            })
+            .collect();
+        assert_eq!(ordered, paths);
+    }
+}
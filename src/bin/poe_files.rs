@@ -1,15 +1,34 @@
-use std::path::PathBuf;
+use std::{
+    path::{Path, PathBuf},
+    time::Duration,
+};
 
-use anyhow::{ensure, Context, Result};
+use anyhow::{bail, ensure, Context, Result};
 use clap::{ArgGroup, Parser, Subcommand};
 use glob::Pattern;
 use poe_tools::{
     bundle_fs::FS,
-    bundle_loader::cdn_base_url,
+    bundle_loader::cdn_base_urls,
+    bundle_source::{CdnSource, GgpkSource, SteamSource},
+    cdn_cache::PrunePolicy,
     commands::{
-        cat::cat_file, dump_art::extract_art, dump_tables::dump_tables, extract::extract_files,
-        list::list_files, Patch,
+        cache::{cache_size, clear_cache, list_cache, prune_cache},
+        cat::cat_file,
+        dump_art::extract_art,
+        dump_tables::dump_tables,
+        extract::extract_files,
+        index_info::index_info,
+        list::list_files,
+        orphans::find_orphans,
+        prefetch::prefetch_bundles,
+        replace::{replace_files, restore_files},
+        verify::verify_bundles,
+        version::print_versions,
+        Patch,
     },
+    file_system::FileSystem,
+    ggpk::GGPK,
+    overlay_fs::OverlayFS,
 };
 
 #[derive(Debug, Subcommand)]
@@ -19,6 +38,10 @@ enum Command {
         /// Glob pattern to filter the list of files
         #[clap(default_value = "*")]
         glob: Pattern,
+
+        /// Show which overlay folder each file comes from
+        #[arg(long)]
+        origin: bool,
     },
     /// Extract matched files to a folder
     Extract {
@@ -49,6 +72,84 @@ enum Command {
         #[clap(default_value = "*.dds")]
         glob: Pattern,
     },
+    /// Override virtual files in a Steam install with local files
+    Replace {
+        /// Folder mirroring the virtual file layout, e.g. `<folder>/data/mods.datc64`
+        replacement_folder: PathBuf,
+    },
+    /// Undo a previous replace, restoring the original index
+    Restore,
+    /// List file hashes that no known path produces, as `hash bundle offset size`, and check
+    /// each path rep's hash against its directory
+    Orphans,
+    /// Summarise the index and its bundles
+    IndexInfo {
+        /// Print the summary as JSON
+        #[arg(long)]
+        json: bool,
+
+        /// Also read every bundle's header for block statistics. This downloads every bundle when
+        /// using the CDN.
+        #[arg(long)]
+        bundles: bool,
+
+        /// Number of directories to show, largest first
+        #[arg(long, default_value_t = 10)]
+        top: usize,
+    },
+    /// Fully decode every bundle and check it against the index. Exits with an error if anything
+    /// is corrupt.
+    Verify,
+    /// Download every bundle needed by the matched files, so later commands can run offline
+    Prefetch {
+        /// Glob pattern to filter the list of files
+        #[clap(default_value = "*")]
+        glob: Pattern,
+
+        /// Number of bundles to download at once
+        #[arg(long, default_value_t = 8)]
+        jobs: usize,
+
+        /// Cap on the combined download rate, in KiB per second
+        #[arg(long)]
+        max_bandwidth: Option<u64>,
+    },
+    /// Print the live version of each game and its CDN URLs, as reported by the patch servers
+    Version,
+    /// Inspect or clean up the download cache
+    Cache {
+        #[command(subcommand)]
+        action: CacheAction,
+    },
+}
+
+#[derive(Debug, Subcommand)]
+enum CacheAction {
+    /// List cached patch versions, most recently downloaded first
+    List,
+    /// Show how much space the cache takes up
+    Size,
+    /// Remove cached patch versions. Each limit given applies on its own. Parsed indexes that no
+    /// remaining version uses, such as those from older Steam or GGPK patches, go as well.
+    Prune {
+        /// Remove versions not downloaded into for this many days
+        #[arg(long)]
+        older_than: Option<u64>,
+
+        /// Keep only this many of the most recently downloaded versions
+        #[arg(long)]
+        keep: Option<usize>,
+
+        /// Remove the least recently downloaded versions until the rest fit in this many MiB
+        #[arg(long)]
+        max_size: Option<u64>,
+
+        /// Only print what would be removed
+        #[arg(long)]
+        dry_run: bool,
+    },
+    /// Remove everything from the cache
+    Clear,
 }
 
 /// A simple CLI tool that extracts the virtual filenames from PoE data files.
@@ -58,25 +159,37 @@ enum Command {
     name = "poe_files",
     group(
         ArgGroup::new("source")
-        .args(&["steam", "cache_dir"])
+        .args(&["steam", "ggpk"])
         .required(false) // At least one is not required, but they are mutually exclusive
         .multiple(false) // Only one can be used at a time
     )
 )]
 #[clap(version)]
 struct Cli {
-    /// Specify the patch version (1, 2, or specific_patch)
-    #[arg(long, required = true)]
-    patch: Patch,
+    /// Specify the patch version (1, 2, or specific_patch). Required by every command except
+    /// `version`
+    #[arg(long)]
+    patch: Option<Patch>,
 
     /// Specify the Steam folder path (optional)
     #[arg(long)]
     steam: Option<PathBuf>,
 
-    /// Specify the cache directory (optional)
+    /// Specify the cache directory, used for CDN downloads and for parsed indexes from every
+    /// source (optional)
     #[arg(long)]
     cache_dir: Option<PathBuf>,
 
+    /// Folder of loose files to layer over the game files. Can be given multiple times, later
+    /// folders take priority (optional)
+    #[arg(long)]
+    overlay: Vec<PathBuf>,
+
+    /// Specify a Content.ggpk file to read from, either a standalone client install or a legacy
+    /// archive (optional)
+    #[arg(long)]
+    ggpk: Option<PathBuf>,
+
     #[command(subcommand)]
     command: Command,
 }
@@ -85,6 +198,7 @@ struct Cli {
 enum Source {
     Cdn { cache_dir: PathBuf },
     Steam { steam_folder: PathBuf },
+    Ggpk { ggpk_file: PathBuf },
 }
 
 #[derive(Debug)]
@@ -93,58 +207,200 @@ struct Args {
     source: Source,
     command: Command,
     cache_dir: PathBuf,
+    overlays: Vec<PathBuf>,
+}
+
+/// The cache directory given, or the platform's default
+fn resolve_cache_dir(cache_dir: Option<PathBuf>) -> Result<PathBuf> {
+    match cache_dir {
+        Some(cache_dir) => Ok(cache_dir),
+        None => Ok(dirs::cache_dir()
+            .context("No default cache directory on this platform, use --cache-dir")?
+            .join("poe_data_tools")),
+    }
 }
 
 /// Validates user input and constructs a valid input state
-fn parse_args() -> Result<Args> {
-    let cli = Cli::parse();
+fn parse_args(cli: Cli) -> Result<Args> {
+    let patch = cli.patch.context("--patch is required")?;
 
-    let cache_dir = cli
-        .cache_dir
-        .unwrap_or_else(|| dirs::cache_dir().unwrap().join("poe_data_tools"));
+    let cache_dir = resolve_cache_dir(cli.cache_dir)?;
 
     let source = if let Some(steam_folder) = cli.steam {
         ensure!(steam_folder.exists(), "Steam folder doesn't exist");
         Source::Steam { steam_folder }
+    } else if let Some(ggpk_file) = cli.ggpk {
+        ensure!(ggpk_file.exists(), "GGPK file doesn't exist");
+        Source::Ggpk { ggpk_file }
     } else {
         Source::Cdn {
             cache_dir: cache_dir.clone(),
         }
     };
 
-    if matches!(source, Source::Steam { .. }) {
+    if matches!(source, Source::Steam { .. } | Source::Ggpk { .. }) {
         ensure!(
-            !matches!(cli.patch, Patch::Specific { .. }),
-            "When using steam, specific patch versions are not supported."
+            !matches!(patch, Patch::Specific { .. }),
+            "When using a local install, specific patch versions are not supported."
         );
     }
 
     Ok(Args {
-        patch: cli.patch,
+        patch,
         source,
         command: cli.command,
         cache_dir,
+        overlays: cli.overlay,
     })
 }
 
-fn main() -> Result<()> {
-    let args = parse_args()?;
+/// Location of the bundle index within a standalone client's GGPK
+const BUNDLED_INDEX: &str = "Bundles2/_.index.bin";
+
+/// Version string the CDN knows a patch by
+fn cdn_version(patch: &Patch) -> &str {
+    match patch {
+        Patch::One => "1",
+        Patch::Two => "2",
+        Patch::Specific(v) => v,
+    }
+}
 
-    let mut fs = match args.source {
+/// Open the bundle file system for the chosen source
+fn init_bundle_fs(source: Source, patch: &Patch, cache_dir: &Path) -> Result<FS> {
+    match source {
         Source::Cdn { cache_dir } => {
-            let version_string = match &args.patch {
-                Patch::One => "1",
-                Patch::Two => "2",
-                Patch::Specific(v) => v,
-            };
-            FS::from_cdn(&cdn_base_url(&cache_dir, version_string)?, &cache_dir)
+            FS::from_cdn(&cdn_base_urls(&cache_dir, cdn_version(patch))?, &cache_dir)
+        }
+        Source::Steam { steam_folder } => {
+            FS::from_source_cached(SteamSource::new(&steam_folder), cache_dir)
+        }
+        Source::Ggpk { ggpk_file } => {
+            let ggpk = GGPK::open(&ggpk_file)?;
+            ensure!(
+                ggpk.exists(BUNDLED_INDEX),
+                "GGPK file is a legacy archive without bundles"
+            );
+            FS::from_source_cached(GgpkSource::new(ggpk), cache_dir)
+        }
+    }
+}
+
+/// Open the file system for the chosen source
+fn init_fs(source: Source, patch: &Patch, cache_dir: &Path) -> Result<Box<dyn FileSystem>> {
+    if let Source::Ggpk { ggpk_file } = &source {
+        // The standalone client keeps its bundles inside the GGPK, older patches don't
+        let ggpk = GGPK::open(ggpk_file)?;
+        if !ggpk.exists(BUNDLED_INDEX) {
+            return Ok(Box::new(ggpk));
+        }
+        return Ok(Box::new(FS::from_source_cached(
+            GgpkSource::new(ggpk),
+            cache_dir,
+        )?));
+    }
+
+    Ok(Box::new(init_bundle_fs(source, patch, cache_dir)?))
+}
+
+fn main() -> Result<()> {
+    let cli = Cli::parse();
+
+    // Querying the patch servers doesn't need a file system, or even a patch
+    if let Command::Version = cli.command {
+        return print_versions().context("Version command failed");
+    }
+
+    // Cache maintenance works on the cache directory alone
+    if let Command::Cache { action } = &cli.command {
+        let cache_dir = resolve_cache_dir(cli.cache_dir)?;
+        return match action {
+            CacheAction::List => list_cache(&cache_dir),
+            CacheAction::Size => cache_size(&cache_dir),
+            CacheAction::Prune {
+                older_than,
+                keep,
+                max_size,
+                dry_run,
+            } => {
+                let policy = PrunePolicy {
+                    max_age: older_than.map(|days| Duration::from_secs(days * 24 * 60 * 60)),
+                    keep: *keep,
+                    max_size: max_size.map(|mib| mib.saturating_mul(1024 * 1024)),
+                };
+                prune_cache(&cache_dir, &policy, *dry_run)
+            }
+            CacheAction::Clear => clear_cache(&cache_dir),
+        }
+        .context("Cache command failed");
+    }
+
+    let args = parse_args(cli)?;
+
+    // Modding commands work on the install directly rather than through the file system
+    match (&args.command, &args.source) {
+        (Command::Replace { replacement_folder }, Source::Steam { steam_folder }) => {
+            return replace_files(steam_folder, replacement_folder)
+                .context("Replace command failed");
+        }
+        (Command::Restore, Source::Steam { steam_folder }) => {
+            return restore_files(steam_folder).context("Restore command failed");
+        }
+        (Command::Replace { .. } | Command::Restore, _) => {
+            bail!("Replacing files is only supported with --steam")
         }
-        Source::Steam { steam_folder } => FS::from_steam(steam_folder),
+        _ => {}
+    }
+
+    // Prefetching fills the CDN cache, with its own limits on downloads
+    if let Command::Prefetch {
+        glob,
+        jobs,
+        max_bandwidth,
+    } = &args.command
+    {
+        let Source::Cdn { cache_dir } = &args.source else {
+            bail!("Prefetching is only supported with the CDN")
+        };
+        let mut source = CdnSource::new(
+            &cdn_base_urls(cache_dir, cdn_version(&args.patch))?,
+            cache_dir,
+        )?;
+        if let Some(max_bandwidth) = max_bandwidth {
+            source = source.with_bandwidth_limit(max_bandwidth.saturating_mul(1024));
+        }
+        let fs = FS::from_source_cached(source, cache_dir)
+            .context("Failed to initialise file system")?;
+        return prefetch_bundles(&fs, glob, *jobs).context("Prefetch command failed");
+    }
+
+    // Analysis commands need the bundle index itself
+    if matches!(
+        args.command,
+        Command::Orphans | Command::IndexInfo { .. } | Command::Verify
+    ) {
+        let fs = init_bundle_fs(args.source, &args.patch, &args.cache_dir)
+            .context("Failed to initialise file system")?;
+        return match args.command {
+            Command::Orphans => find_orphans(&fs).context("Orphans command failed"),
+            Command::IndexInfo { json, bundles, top } => {
+                index_info(&fs, json, bundles, top).context("Index info command failed")
+            }
+            Command::Verify => verify_bundles(&fs).context("Verify command failed"),
+            _ => unreachable!(),
+        };
+    }
+
+    let mut fs = init_fs(args.source, &args.patch, &args.cache_dir)
+        .context("Failed to initialise file system")?;
+    if !args.overlays.is_empty() {
+        fs = Box::new(OverlayFS::new(fs, args.overlays).context("Failed to load overlays")?);
     }
-    .context("Failed to initialise file system")?;
 
     match args.command {
-        Command::List { glob } => list_files(&fs, &glob).context("List command failed")?,
+        Command::List { glob, origin } => {
+            list_files(&fs, &glob, origin).context("List command failed")?
+        }
         Command::Cat { path } => cat_file(&mut fs, &path).context("Cat command failed")?,
         Command::Extract {
             glob,
@@ -159,6 +415,14 @@ fn main() -> Result<()> {
             output_folder,
             glob,
         } => extract_art(&mut fs, &glob, &output_folder).context("Dump Art command failed")?,
+        Command::Replace { .. }
+        | Command::Restore
+        | Command::Orphans
+        | Command::IndexInfo { .. }
+        | Command::Verify
+        | Command::Prefetch { .. }
+        | Command::Version
+        | Command::Cache { .. } => unreachable!(),
     }
 
     Ok(())
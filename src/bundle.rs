@@ -1,7 +1,8 @@
-use std::{fs, path::Path};
+use std::{fs::File, path::Path};
 
-use anyhow::{anyhow, Context, Result};
+use anyhow::{ensure, Context, Result};
 use bytes::Bytes;
+use memmap2::Mmap;
 use nom::{
     bytes::complete::take,
     multi::count,
@@ -9,10 +10,8 @@ use nom::{
     IResult,
 };
 use oozextract::Extractor;
-use rayon::iter::{IndexedParallelIterator, IntoParallelIterator, ParallelIterator};
-use url::Url;
 
-use crate::bundle_loader::CDNLoader;
+use crate::parse_error::ParseError;
 
 /// Encoded as a u32
 #[derive(Debug)]
@@ -46,52 +45,113 @@ pub struct HeadPayload {
 #[derive(Debug)]
 pub struct Bundle {
     pub head: HeadPayload,
-    pub blocks: Vec<Vec<u8>>,
+    pub blocks: Vec<Bytes>,
 }
 
 impl Bundle {
+    /// Parse a bundle without copying, so the blocks point into `bytes`
+    pub fn from_bytes(bytes: Bytes) -> Result<Bundle> {
+        let (input, (head, block_sizes)) = parse_head_payload(&bytes)
+            .map_err(|e| ParseError::from_nom("bundle header", &bytes, e))?;
+        let (_, blocks) = parse_blocks(input, &block_sizes)
+            .map_err(|e| ParseError::from_nom("bundle blocks", &bytes, e))?;
+        let blocks = blocks.into_iter().map(|b| bytes.slice_ref(b)).collect();
+
+        Ok(Bundle { head, blocks })
+    }
+
     /// Return the entire content of the bundle
-    /// todo: decode blocks in parallel
-    ///     Also return a result instead of panicing
-    pub fn read_all(&self) -> Bytes {
+    pub fn read_all(&self) -> Result<Bytes> {
         self.read_range(0, self.head.uncompressed_size as usize)
     }
 
-    pub fn read_range(&self, offset: usize, len: usize) -> Bytes {
+    /// Check that a range lies within the uncompressed content, and that the blocks covering it
+    /// are present
+    pub fn check_range(&self, offset: usize, len: usize) -> Result<()> {
+        let end = offset.checked_add(len).context("Range overflows")?;
+        ensure!(
+            end as u64 <= self.head.uncompressed_size,
+            "Range {}..{} is outside of bundle of size {}",
+            offset,
+            end,
+            self.head.uncompressed_size
+        );
+        ensure!(
+            (1..=MAX_BLOCK_GRANULARITY).contains(&self.head.uncompressed_block_granularity),
+            "Bundle has an invalid block granularity of {}",
+            self.head.uncompressed_block_granularity
+        );
+
+        let expected_blocks = self
+            .head
+            .uncompressed_size
+            .div_ceil(self.head.uncompressed_block_granularity as u64);
+        ensure!(
+            self.blocks.len() as u64 == expected_blocks,
+            "Bundle has {} blocks, expected {}",
+            self.blocks.len(),
+            expected_blocks
+        );
+
+        Ok(())
+    }
+
+    /// Decompress a single block
+    pub fn decode_block(&self, block_index: usize) -> Result<Bytes> {
+        self.check_range(0, 0)?;
         let block_size = self.head.uncompressed_block_granularity as usize;
+        let block = self
+            .blocks
+            .get(block_index)
+            .context("Block index out of range")?;
 
-        // Create a buffer, needs to be block-aligned since we're decoding entire blocks into it
-        let block_start = offset / block_size;
-        let block_end = (offset + len).div_ceil(block_size);
-        let buf_size = (block_end * block_size).min(self.head.uncompressed_size as usize)
-            - block_start * block_size;
-        let mut buf = vec![0; buf_size];
+        // The last block may be shorter
+        let block_len = (self.head.uncompressed_size as usize)
+            .saturating_sub(block_index * block_size)
+            .min(block_size);
+        let mut buf = vec![0; block_len];
 
-        // Chunk into slices which can be written to in parallel
-        let chunks = buf.chunks_mut(block_size).collect::<Vec<_>>();
+        let decoded = Extractor::new()
+            .read_from_slice(block, &mut buf)
+            .with_context(|| format!("Failed to decompress bundle block {}", block_index))?;
+        ensure!(
+            decoded == block_len,
+            "Bundle block {} decoded to {} bytes, expected {}",
+            block_index,
+            decoded,
+            block_len
+        );
 
-        // Decode blocks in parallel
-        chunks
-            .into_par_iter()
-            .zip(&self.blocks[block_start..block_end])
-            .for_each(|(chunk, block)| {
-                let mut ext = Extractor::new();
+        Ok(Bytes::from(buf))
+    }
+
+    pub fn read_range(&self, offset: usize, len: usize) -> Result<Bytes> {
+        self.check_range(offset, len)?;
+        if len == 0 {
+            return Ok(Bytes::new());
+        }
+
+        let block_size = self.head.uncompressed_block_granularity as usize;
+        let block_start = offset / block_size;
+        let block_end = (offset + len).div_ceil(block_size);
 
-                ext.read_from_slice(block, chunk)
-                    .context("Failed to decompress bundle block")
-                    .unwrap();
-            });
+        // Decode one block at a time and grow the buffer as they come out, rather than trusting
+        // the header's size for an allocation up front
+        let mut buf = Vec::new();
+        for block_index in block_start..block_end {
+            buf.extend_from_slice(&self.decode_block(block_index)?);
+        }
 
-        // Grab subset form block aligned buffer
-        Bytes::from(buf).slice(offset % block_size..offset % block_size + len)
+        // Grab subset from block aligned buffer
+        Ok(Bytes::from(buf).slice(offset % block_size..offset % block_size + len))
     }
 }
 
 // Parser for FirstFileEncode
 fn parse_first_file_encode(input: &[u8]) -> IResult<&[u8], FirstFileEncode> {
-    let (input, value) = le_u32(input)?;
+    let (rest, value) = le_u32(input)?;
     match FirstFileEncode::from_u32(value) {
-        Some(encode) => Ok((input, encode)),
+        Some(encode) => Ok((rest, encode)),
         None => Err(nom::Err::Failure(nom::error::Error::new(
             input,
             nom::error::ErrorKind::Alt,
@@ -108,6 +168,17 @@ fn parse_head_payload(input: &[u8]) -> IResult<&[u8], (HeadPayload, Vec<u32>)> {
     let (input, total_payload_size) = le_u64(input)?;
     let (input, block_count) = le_u32(input)?;
     let (input, uncompressed_block_granularity) = le_u32(input)?;
+
+    // Decoding allocates from these sizes, so reject any the blocks can't hold
+    if !(1..=MAX_BLOCK_GRANULARITY).contains(&uncompressed_block_granularity)
+        || uncompressed_size > block_count as u64 * uncompressed_block_granularity as u64
+    {
+        return Err(nom::Err::Failure(nom::error::Error::new(
+            input,
+            nom::error::ErrorKind::Verify,
+        )));
+    }
+
     let (input, _) = take(16usize)(input)?; // Skip bytes 44-60
 
     // Read block sizes (block_count u32s)
@@ -128,13 +199,13 @@ fn parse_head_payload(input: &[u8]) -> IResult<&[u8], (HeadPayload, Vec<u32>)> {
 }
 
 // Parser for blocks
-fn parse_blocks<'a>(input: &'a [u8], block_sizes: &[u32]) -> IResult<&'a [u8], Vec<Vec<u8>>> {
+fn parse_blocks<'a>(input: &'a [u8], block_sizes: &[u32]) -> IResult<&'a [u8], Vec<&'a [u8]>> {
     let mut remaining_input = input;
     let mut blocks = Vec::new();
 
     for &block_size in block_sizes {
         let (input, block_data) = take(block_size)(remaining_input)?;
-        blocks.push(block_data.to_vec());
+        blocks.push(block_data);
         remaining_input = input;
     }
 
@@ -145,27 +216,211 @@ fn parse_blocks<'a>(input: &'a [u8], block_sizes: &[u32]) -> IResult<&'a [u8], V
 pub fn parse_bundle(input: &[u8]) -> IResult<&[u8], Bundle> {
     let (input, (head, block_sizes)) = parse_head_payload(input)?;
     let (input, blocks) = parse_blocks(input, &block_sizes)?;
+    let blocks = blocks.into_iter().map(Bytes::copy_from_slice).collect();
     Ok((input, Bundle { head, blocks }))
 }
 
+/// Memory-map a file from disk
+pub fn map_file(path: &Path) -> Result<Bytes> {
+    let file = File::open(path).context("Failed to open file")?;
+    // Safety: game files aren't expected to be modified while we're reading them
+    let mmap = unsafe { Mmap::map(&file) }.context("Failed to map file")?;
+    Ok(Bytes::from_owner(mmap))
+}
+
 /// Load a bundle file from disk
 pub fn load_bundle_content(path: &Path) -> Result<Bundle> {
-    // todo: figure how to properly do error propogation with nom
-    let bundle_content = fs::read(path).context("Failed to read bundle file")?;
+    let bundle_content = map_file(path).context("Failed to read bundle file")?;
+    Bundle::from_bytes(bundle_content)
+}
+
+/// Block size used by the game when writing bundles
+pub const DEFAULT_BLOCK_GRANULARITY: u32 = 0x40000;
 
-    let (_, bundle) =
-        parse_bundle(&bundle_content).map_err(|_| anyhow!("Failed to parse bundle"))?;
-    Ok(bundle)
+/// Largest block size accepted when reading bundles
+pub const MAX_BLOCK_GRANULARITY: u32 = 0x1000000;
+
+/// Size of an Oodle chunk. Each chunk inside a block carries its own header.
+const OODLE_CHUNK_SIZE: usize = 0x40000;
+
+/// Compresses the blocks of a bundle while it is being serialized
+pub trait BlockEncoder {
+    /// Codec recorded in the bundle header
+    fn encoding(&self) -> FirstFileEncode;
+
+    /// Encode a single block of at most `uncompressed_block_granularity` bytes
+    fn encode(&self, block: &[u8]) -> Result<Vec<u8>>;
 }
 
-// Fetch a bundle file from the CDN (or cache)
-pub fn fetch_bundle_content(base_url: &Url, cache_dir: &Path, path: &Path) -> Result<Bundle> {
-    let bundle_content = CDNLoader::new(base_url, cache_dir.to_str().unwrap())
-        .load(path)
-        .context("Failed to load bundle")?;
+/// Stores blocks as uncompressed Oodle chunks. Any Oodle decoder can read these back, so it's
+/// useful for tests and tools that don't have access to a compressor.
+pub struct StoredEncoder;
+
+impl BlockEncoder for StoredEncoder {
+    fn encoding(&self) -> FirstFileEncode {
+        FirstFileEncode::Kraken6
+    }
 
-    let (_, bundle) =
-        parse_bundle(&bundle_content).map_err(|_| anyhow!("Failed to parse bundle"))?;
+    fn encode(&self, block: &[u8]) -> Result<Vec<u8>> {
+        let mut encoded =
+            Vec::with_capacity(block.len() + 2 * block.len().div_ceil(OODLE_CHUNK_SIZE));
+        for chunk in block.chunks(OODLE_CHUNK_SIZE) {
+            // 0xCC: magic nibble, restart decoder + uncompressed flags. 0x06: Kraken decoder
+            encoded.extend_from_slice(&[0xCC, 0x06]);
+            encoded.extend_from_slice(chunk);
+        }
+        Ok(encoded)
+    }
+}
 
-    Ok(bundle)
+/// Serialize raw content into a bundle, splitting it into blocks of `granularity` bytes
+pub fn serialize_bundle(
+    content: &[u8],
+    granularity: u32,
+    encoder: &impl BlockEncoder,
+) -> Result<Vec<u8>> {
+    ensure!(granularity > 0, "Block granularity must be non-zero");
+
+    let blocks = content
+        .chunks(granularity as usize)
+        .map(|block| encoder.encode(block))
+        .collect::<Result<Vec<_>>>()
+        .context("Failed to encode bundle block")?;
+
+    let block_sizes = blocks
+        .iter()
+        .map(|block| u32::try_from(block.len()))
+        .collect::<Result<Vec<_>, _>>()
+        .context("Encoded block too large")?;
+    let block_count = u32::try_from(blocks.len()).context("Too many blocks")?;
+    let uncompressed_size = content.len() as u64;
+    let total_payload_size = blocks.iter().map(|b| b.len() as u64).sum::<u64>();
+    let head_size = 48 + 4 * block_count;
+
+    let mut bytes = Vec::with_capacity(12 + head_size as usize + total_payload_size as usize);
+
+    // Preamble
+    bytes.extend(
+        u32::try_from(uncompressed_size)
+            .context("Bundle content too large")?
+            .to_le_bytes(),
+    );
+    bytes.extend(
+        u32::try_from(total_payload_size)
+            .context("Bundle payload too large")?
+            .to_le_bytes(),
+    );
+    bytes.extend(head_size.to_le_bytes());
+
+    // Head payload
+    bytes.extend((encoder.encoding() as u32).to_le_bytes());
+    bytes.extend(1u32.to_le_bytes());
+    bytes.extend(uncompressed_size.to_le_bytes());
+    bytes.extend(total_payload_size.to_le_bytes());
+    bytes.extend(block_count.to_le_bytes());
+    bytes.extend(granularity.to_le_bytes());
+    bytes.extend([0; 16]);
+    block_sizes
+        .iter()
+        .for_each(|size| bytes.extend(size.to_le_bytes()));
+
+    // Blocks
+    blocks.iter().for_each(|block| bytes.extend(block));
+
+    Ok(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use bytes::Bytes;
+
+    use super::{parse_bundle, serialize_bundle, Bundle, StoredEncoder};
+
+    fn sample_content(len: usize) -> Vec<u8> {
+        (0..len).map(|i| (i * 7 % 251) as u8).collect()
+    }
+
+    #[test]
+    fn round_trip_small_blocks() {
+        let content = sample_content(3500);
+        let bytes = serialize_bundle(&content, 1000, &StoredEncoder).unwrap();
+
+        let (rest, bundle) = parse_bundle(&bytes).unwrap();
+        assert!(rest.is_empty());
+        assert_eq!(bundle.blocks.len(), 4);
+        assert_eq!(bundle.head.uncompressed_size, 3500);
+        assert_eq!(bundle.read_all().unwrap(), content);
+        assert_eq!(bundle.read_range(1500, 700).unwrap(), content[1500..2200]);
+    }
+
+    #[test]
+    fn reject_out_of_bounds_range() {
+        let content = sample_content(2500);
+        let bytes = serialize_bundle(&content, 1000, &StoredEncoder).unwrap();
+        let (_, mut bundle) = parse_bundle(&bytes).unwrap();
+
+        assert!(bundle.read_range(2000, 501).is_err());
+        assert!(bundle.read_range(usize::MAX, 2).is_err());
+        assert_eq!(bundle.read_range(2500, 0).unwrap().len(), 0);
+
+        // Missing blocks are reported rather than panicking
+        bundle.blocks.pop();
+        assert!(bundle.read_range(0, 10).is_err());
+    }
+
+    #[test]
+    fn reject_oversized_header() {
+        let mut bytes = serialize_bundle(&sample_content(100), 1000, &StoredEncoder).unwrap();
+
+        // A granularity of 4 GiB, which would size the decode buffer
+        bytes[40..44].copy_from_slice(&u32::MAX.to_le_bytes());
+        assert!(parse_bundle(&bytes).is_err());
+
+        // A size larger than the single block can hold
+        bytes[40..44].copy_from_slice(&1000u32.to_le_bytes());
+        bytes[20..28].copy_from_slice(&u64::MAX.to_le_bytes());
+        assert!(Bundle::from_bytes(Bytes::from(bytes)).is_err());
+    }
+
+    #[test]
+    fn reject_short_block() {
+        let content = sample_content(2500);
+        let bytes = serialize_bundle(&content, 1000, &StoredEncoder).unwrap();
+        let (_, mut bundle) = parse_bundle(&bytes).unwrap();
+
+        // The last block decodes to nothing rather than the 500 bytes the header promises
+        *bundle.blocks.last_mut().unwrap() = Bytes::new();
+        assert!(bundle.decode_block(2).is_err());
+        assert!(bundle.read_range(2100, 100).is_err());
+        assert!(bundle.read_all().is_err());
+        assert_eq!(bundle.read_range(0, 2000).unwrap(), content[..2000]);
+    }
+
+    #[test]
+    fn blocks_share_buffer() {
+        let content = sample_content(2500);
+        let bytes = Bytes::from(serialize_bundle(&content, 1000, &StoredEncoder).unwrap());
+
+        let bundle = Bundle::from_bytes(bytes.clone()).unwrap();
+        let range = bytes.as_ptr_range();
+        assert!(bundle
+            .blocks
+            .iter()
+            .all(|b| range.contains(&b.as_ptr()) && b.as_ptr_range().end <= range.end));
+        assert_eq!(bundle.read_range(900, 1200).unwrap(), content[900..2100]);
+    }
+
+    #[test]
+    fn round_trip_multi_chunk_blocks() {
+        let content = sample_content(0x40000 * 3 + 123);
+        let bytes = serialize_bundle(&content, 0x80000, &StoredEncoder).unwrap();
+
+        let (_, bundle) = parse_bundle(&bytes).unwrap();
+        assert_eq!(bundle.blocks.len(), 2);
+        assert_eq!(bundle.read_all().unwrap(), content);
+        assert_eq!(
+            bundle.read_range(0x40000 - 10, 0x40000).unwrap(),
+            content[0x40000 - 10..0x80000 - 10]
+        );
+    }
 }
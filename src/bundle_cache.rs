@@ -0,0 +1,221 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    mem::size_of,
+    sync::Arc,
+};
+
+use bytes::Bytes;
+
+use crate::bundle::Bundle;
+
+/// Default memory budget for the cache
+pub const DEFAULT_CACHE_BUDGET: usize = 256 * 1024 * 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+enum CacheKey {
+    Bundle(u32),
+    Block(u32, usize),
+}
+
+enum CacheEntry {
+    Bundle(Arc<Bundle>),
+    Block(Bytes),
+}
+
+impl CacheEntry {
+    /// Number of heap bytes this entry counts against the budget. A parsed bundle's blocks point
+    /// into the source's file mapping, so only its header and block table count.
+    fn size(&self) -> usize {
+        match self {
+            CacheEntry::Bundle(bundle) => {
+                size_of::<Bundle>() + bundle.blocks.capacity() * size_of::<Bytes>()
+            }
+            CacheEntry::Block(block) => block.len(),
+        }
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy)]
+pub struct CacheStats {
+    pub bundle_hits: u64,
+    pub bundle_misses: u64,
+    pub block_hits: u64,
+    pub block_misses: u64,
+    pub evictions: u64,
+    pub entries: usize,
+    pub bytes_used: usize,
+}
+
+/// Least-recently-used cache of parsed bundles and decompressed blocks, bounded by a byte budget
+pub struct BundleCache {
+    budget: usize,
+    used: usize,
+    tick: u64,
+    entries: HashMap<CacheKey, (CacheEntry, u64)>,
+    recency: BTreeMap<u64, CacheKey>,
+    stats: CacheStats,
+}
+
+impl BundleCache {
+    pub fn new(budget: usize) -> Self {
+        Self {
+            budget,
+            used: 0,
+            tick: 0,
+            entries: HashMap::new(),
+            recency: BTreeMap::new(),
+            stats: CacheStats::default(),
+        }
+    }
+
+    /// Change the budget, evicting entries if the cache is now over it
+    pub fn set_budget(&mut self, budget: usize) {
+        self.budget = budget;
+        self.evict_for(0);
+    }
+
+    pub fn stats(&self) -> CacheStats {
+        CacheStats {
+            entries: self.entries.len(),
+            bytes_used: self.used,
+            ..self.stats
+        }
+    }
+
+    pub fn get_bundle(&mut self, bundle_index: u32) -> Option<Arc<Bundle>> {
+        let bundle = match self.touch(&CacheKey::Bundle(bundle_index)) {
+            Some(CacheEntry::Bundle(bundle)) => Some(bundle.clone()),
+            _ => None,
+        };
+
+        if bundle.is_some() {
+            self.stats.bundle_hits += 1;
+        } else {
+            self.stats.bundle_misses += 1;
+        }
+        bundle
+    }
+
+    pub fn insert_bundle(&mut self, bundle_index: u32, bundle: Arc<Bundle>) {
+        self.insert(CacheKey::Bundle(bundle_index), CacheEntry::Bundle(bundle));
+    }
+
+    pub fn get_block(&mut self, bundle_index: u32, block_index: usize) -> Option<Bytes> {
+        let block = match self.touch(&CacheKey::Block(bundle_index, block_index)) {
+            Some(CacheEntry::Block(block)) => Some(block.clone()),
+            _ => None,
+        };
+
+        if block.is_some() {
+            self.stats.block_hits += 1;
+        } else {
+            self.stats.block_misses += 1;
+        }
+        block
+    }
+
+    pub fn insert_block(&mut self, bundle_index: u32, block_index: usize, block: Bytes) {
+        self.insert(
+            CacheKey::Block(bundle_index, block_index),
+            CacheEntry::Block(block),
+        );
+    }
+
+    /// Look up an entry, marking it as most recently used
+    fn touch(&mut self, key: &CacheKey) -> Option<&CacheEntry> {
+        let (entry, tick) = self.entries.get_mut(key)?;
+        self.recency.remove(&*tick);
+        self.tick += 1;
+        *tick = self.tick;
+        self.recency.insert(self.tick, *key);
+        Some(&*entry)
+    }
+
+    fn insert(&mut self, key: CacheKey, entry: CacheEntry) {
+        // Entries that could never fit are simply not cached
+        let size = entry.size();
+        if size > self.budget {
+            return;
+        }
+
+        if let Some((old, tick)) = self.entries.remove(&key) {
+            self.used -= old.size();
+            self.recency.remove(&tick);
+        }
+        self.evict_for(size);
+
+        self.tick += 1;
+        self.recency.insert(self.tick, key);
+        self.entries.insert(key, (entry, self.tick));
+        self.used += size;
+    }
+
+    /// Evict least recently used entries until `size` more bytes fit in the budget
+    fn evict_for(&mut self, size: usize) {
+        while self.used + size > self.budget {
+            let Some((_, key)) = self.recency.pop_first() else {
+                break;
+            };
+            if let Some((entry, _)) = self.entries.remove(&key) {
+                self.used -= entry.size();
+                self.stats.evictions += 1;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use bytes::Bytes;
+
+    use super::BundleCache;
+    use crate::bundle::{serialize_bundle, Bundle, StoredEncoder};
+
+    #[test]
+    fn evicts_least_recently_used() {
+        let mut cache = BundleCache::new(30);
+        cache.insert_block(0, 0, Bytes::from(vec![0; 10]));
+        cache.insert_block(0, 1, Bytes::from(vec![1; 10]));
+        cache.insert_block(0, 2, Bytes::from(vec![2; 10]));
+
+        // Touch the first block so the second is the oldest
+        assert!(cache.get_block(0, 0).is_some());
+        cache.insert_block(1, 0, Bytes::from(vec![3; 10]));
+
+        assert!(cache.get_block(0, 1).is_none());
+        assert!(cache.get_block(0, 0).is_some());
+        assert!(cache.get_block(1, 0).is_some());
+
+        let stats = cache.stats();
+        assert_eq!(stats.block_hits, 3);
+        assert_eq!(stats.block_misses, 1);
+        assert_eq!(stats.evictions, 1);
+        assert_eq!(stats.bytes_used, 30);
+    }
+
+    #[test]
+    fn skips_oversized_entries() {
+        let mut cache = BundleCache::new(5);
+        cache.insert_block(0, 0, Bytes::from(vec![0; 10]));
+        assert!(cache.get_block(0, 0).is_none());
+        assert_eq!(cache.stats().entries, 0);
+    }
+
+    #[test]
+    fn bundles_only_charge_their_block_table() {
+        let mut cache = BundleCache::new(2000);
+        for i in 0..3 {
+            cache.insert_block(0, i, Bytes::from(vec![0; 500]));
+        }
+
+        let bytes = serialize_bundle(&[0; 1000], 1000, &StoredEncoder).unwrap();
+        let bundle = Bundle::from_bytes(Bytes::from(bytes)).unwrap();
+        cache.insert_bundle(1, Arc::new(bundle));
+
+        assert!(cache.get_bundle(1).is_some());
+        assert_eq!(cache.stats().evictions, 0);
+        assert!(cache.stats().bytes_used < 2000);
+    }
+}
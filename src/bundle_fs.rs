@@ -1,35 +1,80 @@
 use std::{
-    collections::HashMap,
-    hash::{BuildHasher, Hasher},
+    collections::{BTreeSet, HashMap},
     path::{Path, PathBuf},
+    sync::{Arc, Mutex, OnceLock},
 };
 
-use anyhow::{anyhow, Context, Result};
+use anyhow::{ensure, Context, Result};
 use bytes::Bytes;
-use iterators_extended::bucket::Bucket;
+use rayon::iter::{IndexedParallelIterator, IntoParallelIterator, ParallelIterator};
 use url::Url;
 
 use crate::{
-    bundle::{fetch_bundle_content, load_bundle_content},
-    bundle_index::{fetch_index_file, load_index_file, BundleIndex},
-    hasher::BuildMurmurHash64A,
+    batch_read::{BatchRead, DEFAULT_BATCH_READ_LIMIT},
+    bundle::Bundle,
+    bundle_cache::{BundleCache, CacheStats, DEFAULT_CACHE_BUDGET},
+    bundle_index::{detect_hash_algorithm, index_from_bytes, BundleIndex, BundleInfo, FileInfo},
+    bundle_source::{BundleSource, CdnSource, GgpkSource, SteamSource},
+    file_system::{BatchReadItem, FileSystem},
+    ggpk::GGPK,
+    hasher::PathHashAlgorithm,
+    index_analysis::{check_path_reps, orphaned_files, OrphanedFile, PathRepMismatch},
+    index_cache::{CachedIndex, IndexCache},
     path::parse_paths,
+    path_tree::{DirEntry, PathTree},
+    virtual_file::VirtualFile,
 };
 
+/// Size and location of a file within the bundles
+#[derive(Debug, Clone)]
+pub struct FileMetadata {
+    pub size: u32,
+    pub bundle: String,
+    pub offset: u32,
+}
+
 pub struct FS {
     index: BundleIndex,
     lut: HashMap<u64, usize>,
-    steam_folder: Option<PathBuf>,
-    base_url: Option<Url>,
-    cache_dir: Option<PathBuf>,
+    hash_algorithm: PathHashAlgorithm,
+    source: Box<dyn BundleSource>,
+    cache: Mutex<BundleCache>,
+    paths: OnceLock<Vec<String>>,
+    tree: OnceLock<PathTree>,
+    batch_read_limit: usize,
 }
 
 impl FS {
-    /// Initialise a file system over a steam folder
-    pub fn from_steam(steam_folder: PathBuf) -> Result<FS> {
-        let index_path = steam_folder.as_path().join("Bundles2/_.index.bin");
-        let index = load_index_file(&index_path).context("Failed to load bundle index")?;
+    /// Initialise a file system over any source of bundles
+    pub fn from_source(source: impl BundleSource + 'static) -> Result<FS> {
+        let index = source
+            .load_index()
+            .and_then(index_from_bytes)
+            .context("Failed to load bundle index")?;
+
+        Ok(Self::new(index, OnceLock::new(), Box::new(source)))
+    }
+
+    /// Initialise a file system over any source of bundles, reusing the parsed index and path
+    /// list from `cache_dir` when the index file has been seen before
+    pub fn from_source_cached(source: impl BundleSource + 'static, cache_dir: &Path) -> Result<FS> {
+        let raw_index = source.load_index().context("Failed to load bundle index")?;
+
+        let index_cache = IndexCache::new(cache_dir);
+        if let Some(CachedIndex { index, paths }) = index_cache.load(&raw_index) {
+            return Ok(Self::new(index, paths.into(), Box::new(source)));
+        }
 
+        let index = index_from_bytes(raw_index.clone()).context("Failed to load bundle index")?;
+        let fs = Self::new(index, OnceLock::new(), Box::new(source));
+        if let Err(e) = index_cache.store(&raw_index, &fs.index, fs.paths()) {
+            eprintln!("Failed to cache parsed index: {:?}", e);
+        }
+
+        Ok(fs)
+    }
+
+    fn new(index: BundleIndex, paths: OnceLock<Vec<String>>, source: Box<dyn BundleSource>) -> FS {
         let lut = index
             .files
             .iter()
@@ -37,168 +82,355 @@ impl FS {
             .map(|(i, f)| (f.hash, i))
             .collect();
 
-        Ok(FS {
+        let hash_algorithm = detect_hash_algorithm(&index);
+
+        FS {
             index,
             lut,
-            steam_folder: Some(steam_folder.clone()),
-            base_url: None,
-            cache_dir: None,
-        })
+            hash_algorithm,
+            source,
+            cache: Mutex::new(BundleCache::new(DEFAULT_CACHE_BUDGET)),
+            paths,
+            tree: OnceLock::new(),
+            batch_read_limit: DEFAULT_BATCH_READ_LIMIT,
+        }
+    }
+
+    /// Initialise a file system over a steam folder
+    pub fn from_steam(steam_folder: PathBuf) -> Result<FS> {
+        Self::from_source(SteamSource::new(&steam_folder))
     }
 
     /// Initialise a file system using the CDN background
-    pub fn from_cdn(base_url: &Url, cache_dir: &Path) -> Result<FS> {
-        let index = fetch_index_file(
-            base_url,
-            cache_dir,
-            PathBuf::from("Bundles2/_.index.bin").as_ref(),
-        )
-        .context("Failed to load bundle index")?;
+    pub fn from_cdn(base_urls: &[Url], cache_dir: &Path) -> Result<FS> {
+        Self::from_source_cached(CdnSource::new(base_urls, cache_dir)?, cache_dir)
+    }
 
-        let lut = index
-            .files
-            .iter()
-            .enumerate()
-            .map(|(i, f)| (f.hash, i))
-            .collect();
+    /// Initialise a file system over the bundles stored inside a standalone client's GGPK
+    pub fn from_ggpk(ggpk: GGPK) -> Result<FS> {
+        Self::from_source(GgpkSource::new(ggpk))
+    }
 
-        Ok(FS {
-            index,
-            lut,
-            steam_folder: None,
-            base_url: Some(base_url.clone()),
-            cache_dir: Some(cache_dir.to_path_buf()),
+    /// Set the number of bytes of parsed bundles and decompressed blocks to keep in memory
+    pub fn with_cache_budget(mut self, budget: usize) -> Self {
+        self.cache.get_mut().unwrap().set_budget(budget);
+        self
+    }
+
+    /// Set the number of decoded bytes a batch read may hold before they are yielded
+    pub fn with_batch_read_limit(mut self, limit: usize) -> Self {
+        self.batch_read_limit = limit;
+        self
+    }
+
+    /// Hash algorithm the index uses for its paths
+    pub fn hash_algorithm(&self) -> PathHashAlgorithm {
+        self.hash_algorithm
+    }
+
+    /// Hit/miss statistics for the bundle cache
+    pub fn cache_stats(&self) -> CacheStats {
+        self.cache.lock().unwrap().stats()
+    }
+
+    /// Every path in the index, expanded from the path reps on first use. Path reps that fail to
+    /// parse are skipped with a warning.
+    pub fn paths(&self) -> &[String] {
+        self.paths.get_or_init(|| {
+            self.index
+                .paths
+                .iter()
+                .filter_map(|p| {
+                    parse_paths(&self.index.path_rep_bundle, p)
+                        .map_err(|e| eprintln!("Skipping path rep {:016x}: {}", p.hash, e))
+                        .ok()
+                })
+                .flat_map(|p| p.get_paths())
+                .collect()
         })
     }
 
     /// Lists all paths in the index
     pub fn list(&self) -> impl Iterator<Item = String> + '_ {
-        self.index
-            .paths
-            .iter()
-            .flat_map(|p| parse_paths(&self.index.path_rep_bundle, p).get_paths())
+        self.paths().iter().cloned()
     }
 
-    /// Read many files at once, optimising batch loads. Does not preserve order of paths given.
-    pub fn batch_read<'a>(
-        &'a self,
-        paths: &'a [&str],
-    ) -> impl Iterator<Item = Result<(&'a str, Bytes), (&'a str, anyhow::Error)>> {
-        // Get FileInfo's
-        let hash_builder = BuildMurmurHash64A { seed: 0x1337b33f };
-        let (fileinfos, errors) = paths
+    /// The parsed bundle index
+    pub fn index(&self) -> &BundleIndex {
+        &self.index
+    }
+
+    /// File entries that no listed path hashes to
+    pub fn orphaned_files(&self) -> Vec<OrphanedFile> {
+        orphaned_files(&self.index, self.paths(), self.hash_algorithm)
+    }
+
+    /// Path reps whose hash doesn't match the directory they describe
+    pub fn check_path_reps(&self) -> Vec<PathRepMismatch> {
+        check_path_reps(&self.index, self.hash_algorithm)
+    }
+
+    /// Directory tree over the index, built on first use
+    fn tree(&self) -> &PathTree {
+        self.tree.get_or_init(|| {
+            PathTree::build(&self.index, self.paths(), &self.lut, self.hash_algorithm)
+        })
+    }
+
+    /// List the contents of a directory. The root is the empty string.
+    pub fn read_dir(&self, path: &str) -> Result<Vec<DirEntry>> {
+        self.tree()
+            .read_dir(path)
+            .with_context(|| format!("Directory not found: {}", path))
+    }
+
+    /// Whether a file or directory exists
+    pub fn exists(&self, path: &str) -> bool {
+        self.lut.contains_key(&self.hash_algorithm.hash_file(path)) || self.tree().is_dir(path)
+    }
+
+    /// Indices of the bundles holding any of `paths`, in order and without duplicates. Paths
+    /// missing from the index are skipped.
+    pub fn bundles_for(&self, paths: &[&str]) -> Vec<u32> {
+        let bundles = paths
             .iter()
-            .map(|&path| {
-                // Compute hash
-                let mut hasher = hash_builder.build_hasher();
-                hasher.write(path.to_lowercase().as_bytes());
-                let hash = hasher.finish();
-
-                // Look up the file info for this file
-                let fileinfo = self
-                    .lut
-                    .get(&hash)
-                    .map(|i| &self.index.files[*i])
-                    .with_context(|| format!("Path not found in index: {}", path))
-                    .map(|f| (path, f))
-                    .map_err(|e| (path, e));
-
-                fileinfo
+            .filter_map(|path| self.file_info(path).ok())
+            .map(|file| file.bundle_index)
+            .collect::<BTreeSet<_>>();
+        bundles.into_iter().collect()
+    }
+
+    /// Look up the index entry of a file
+    fn file_info(&self, path: &str) -> Result<&FileInfo> {
+        let index = self
+            .lut
+            .get(&self.hash_algorithm.hash_file(path))
+            .with_context(|| format!("Path not found in index: {}", path))?;
+        Ok(&self.index.files[*index])
+    }
+
+    /// Size and location of a file
+    pub fn metadata(&self, path: &str) -> Result<FileMetadata> {
+        let file = self.file_info(path)?;
+
+        Ok(FileMetadata {
+            size: file.size,
+            bundle: self
+                .index
+                .bundles
+                .get(file.bundle_index as usize)
+                .context("Invalid bundle index")?
+                .name
+                .clone(),
+            offset: file.offset,
+        })
+    }
+
+    /// Look up a bundle's entry in the index
+    fn bundle_info(&self, bundle_index: u32) -> Result<&BundleInfo> {
+        self.index
+            .bundles
+            .get(bundle_index as usize)
+            .with_context(|| format!("Bundle index out of range: {}", bundle_index))
+    }
+
+    /// Fully decode a bundle straight from the source, bypassing the cache, and check that it
+    /// matches the size the index expects
+    pub fn verify_bundle(&self, bundle_index: u32) -> Result<()> {
+        let info = self.bundle_info(bundle_index)?;
+        let bundle = self.source.load_bundle(info).and_then(Bundle::from_bytes)?;
+
+        ensure!(
+            bundle.head.uncompressed_size == info.uncompressed_size as u64,
+            "Bundle header says {} bytes uncompressed, index expects {}",
+            bundle.head.uncompressed_size,
+            info.uncompressed_size
+        );
+        bundle.check_range(0, info.uncompressed_size as usize)?;
+
+        // Each block fails to decode unless it comes out at exactly its expected size
+        (0..bundle.blocks.len())
+            .into_par_iter()
+            .try_for_each(|i| bundle.decode_block(i).map(|_| ()))
+    }
+
+    /// Make sure a bundle is available locally without parsing it, downloading it if the source
+    /// is the CDN. Returns the size of the bundle file.
+    pub fn fetch_bundle(&self, bundle_index: u32) -> Result<usize> {
+        let bytes = self.source.load_bundle(self.bundle_info(bundle_index)?)?;
+        Ok(bytes.len())
+    }
+
+    /// Load a bundle, reusing it from the cache if possible
+    pub(crate) fn load_bundle(&self, bundle_index: u32) -> Result<Arc<Bundle>> {
+        if let Some(bundle) = self.cache.lock().unwrap().get_bundle(bundle_index) {
+            return Ok(bundle);
+        }
+
+        let bundle = self
+            .source
+            .load_bundle(self.bundle_info(bundle_index)?)
+            .and_then(Bundle::from_bytes)?;
+
+        let bundle = Arc::new(bundle);
+        self.cache
+            .lock()
+            .unwrap()
+            .insert_bundle(bundle_index, bundle.clone());
+        Ok(bundle)
+    }
+
+    /// Fetch a single decompressed block, reusing it from the cache if possible
+    pub(crate) fn block(
+        &self,
+        bundle_index: u32,
+        bundle: &Bundle,
+        block_index: usize,
+    ) -> Result<Bytes> {
+        if let Some(block) = self
+            .cache
+            .lock()
+            .unwrap()
+            .get_block(bundle_index, block_index)
+        {
+            return Ok(block);
+        }
+
+        let block = bundle.decode_block(block_index)?;
+        self.cache
+            .lock()
+            .unwrap()
+            .insert_block(bundle_index, block_index, block.clone());
+        Ok(block)
+    }
+
+    /// Read a range of a bundle's content, reusing decompressed blocks from the cache
+    pub(crate) fn read_range(
+        &self,
+        bundle_index: u32,
+        bundle: &Bundle,
+        offset: usize,
+        len: usize,
+    ) -> Result<Bytes> {
+        bundle.check_range(offset, len)?;
+        if len == 0 {
+            return Ok(Bytes::new());
+        }
+
+        let block_size = bundle.head.uncompressed_block_granularity as usize;
+        let block_start = offset / block_size;
+        let block_end = (offset + len).div_ceil(block_size);
+
+        // Grab whatever is cached, without holding the lock while decoding
+        let cached = {
+            let mut cache = self.cache.lock().unwrap();
+            (block_start..block_end)
+                .map(|i| cache.get_block(bundle_index, i))
+                .collect::<Vec<_>>()
+        };
+
+        // Decode the rest in parallel
+        let blocks = cached
+            .into_par_iter()
+            .enumerate()
+            .map(|(i, block)| match block {
+                Some(block) => Ok((block, false)),
+                None => bundle
+                    .decode_block(block_start + i)
+                    .map(|block| (block, true)),
             })
-            .bucket_result();
-
-        // Batch them into their bundles
-        let fileinfos =
-            fileinfos
-                .into_iter()
-                .fold(HashMap::<_, Vec<_>>::new(), |mut acc, (path, fileinfo)| {
-                    acc.entry(fileinfo.bundle_index)
-                        .or_default()
-                        .push((path, fileinfo));
-
-                    acc
+            .collect::<Result<Vec<_>>>()?;
+
+        {
+            let mut cache = self.cache.lock().unwrap();
+            blocks
+                .iter()
+                .enumerate()
+                .filter(|(_, (_, decoded))| *decoded)
+                .for_each(|(i, (block, _))| {
+                    cache.insert_block(bundle_index, block_start + i, block.clone())
                 });
+        }
+
+        // Files within a single block can be handed out without copying
+        let start = offset - block_start * block_size;
+        if blocks.len() == 1 {
+            return Ok(blocks[0].0.slice(start..start + len));
+        }
 
-        // Process files bundle-wise
-        let file_contents = fileinfos.into_iter().flat_map(|(bundle_index, files)| {
-            // Load the bundle
-            let bundle_path = format!(
-                "Bundles2/{}.bundle.bin",
-                self.index.bundles[bundle_index as usize].name
-            );
-            let bundle = if let Some(steam_folder) = &self.steam_folder {
-                let bundle_path = steam_folder.join(bundle_path);
-                load_bundle_content(&bundle_path)
-                    .with_context(|| format!("Failed to load bundle file: {:?}", bundle_path))
-            } else {
-                let bundle_path = PathBuf::from(bundle_path);
-                fetch_bundle_content(
-                    self.base_url.as_ref().unwrap(),
-                    self.cache_dir.as_ref().unwrap(),
-                    &bundle_path,
-                )
-                .with_context(|| format!("Failed to fetch bundle file: {:?}", bundle_path))
-            };
-
-            // Read the file contents - todo: see if we can do this lazily instead of
-            // collecting all files within a bundle at once
-            let contents: Vec<_> = match bundle {
-                Ok(b) => files
-                    .into_iter()
-                    .map(|(path, file)| {
-                        Ok((path, b.read_range(file.offset as usize, file.size as usize)))
-                    })
-                    .collect(),
-                Err(e) => files
-                    .into_iter()
-                    .map(|(path, _)| Err((path, anyhow!("{:?}", e))))
-                    .collect(),
-            };
-
-            contents
-        });
-
-        // Add on previous errors
-        errors.into_iter().map(Err).chain(file_contents)
+        let mut buf = Vec::with_capacity(len);
+        for (i, (block, _)) in blocks.iter().enumerate() {
+            let block_offset = (block_start + i) * block_size;
+            let from = offset.max(block_offset) - block_offset;
+            let to = (offset + len - block_offset).min(block.len());
+            buf.extend_from_slice(&block[from..to]);
+        }
+
+        Ok(Bytes::from(buf))
     }
 
-    pub fn read(&self, path: &str) -> Result<Bytes> {
-        // Compute the hash of this file path
-        let hash_builder = BuildMurmurHash64A { seed: 0x1337b33f };
-        let mut hasher = hash_builder.build_hasher();
-        hasher.write(path.to_lowercase().as_bytes());
-        let hash = hasher.finish();
+    /// Read many files at once, optimising batch loads. Does not preserve order of paths given.
+    /// Files are decoded lazily, holding at most the batch read limit in memory at once.
+    pub fn batch_read<'a>(&'a self, paths: &[&'a str]) -> BatchRead<'a> {
+        self.batch_read_inner(paths, false)
+    }
+
+    /// Read many files at once, yielding them in the order the paths were given
+    pub fn batch_read_ordered<'a>(&'a self, paths: &[&'a str]) -> BatchRead<'a> {
+        self.batch_read_inner(paths, true)
+    }
+
+    fn batch_read_inner<'a>(&'a self, paths: &[&'a str], ordered: bool) -> BatchRead<'a> {
+        let files = paths.iter().map(|&path| (path, self.file_info(path)));
+
+        BatchRead::new(self, self.batch_read_limit, files, ordered)
+    }
+
+    /// Open a file for streaming, only decompressing the blocks that get read
+    pub fn open(&self, path: &str) -> Result<VirtualFile<'_>> {
+        let file = self.file_info(path)?;
+        let bundle = self.load_bundle(file.bundle_index)?;
+        bundle.check_range(file.offset as usize, file.size as usize)?;
+
+        Ok(VirtualFile::new(
+            self,
+            file.bundle_index,
+            bundle,
+            file.offset as u64,
+            file.size as u64,
+        ))
+    }
 
+    pub fn read(&self, path: &str) -> Result<Bytes> {
         // Look up the file info for this file
-        let index = self
-            .lut
-            .get(&hash)
-            .with_context(|| format!("Path not found in index: {}", path))?;
-        let file = &self.index.files[*index];
+        let file = self.file_info(path)?;
 
         // Load the bundle
-        let bundle = if let Some(steam_folder) = &self.steam_folder {
-            let bundle_path = steam_folder.join(format!(
-                "Bundles2/{}.bundle.bin",
-                self.index.bundles[file.bundle_index as usize].name
-            ));
-            load_bundle_content(&bundle_path)
-                .with_context(|| format!("Failed to load bundle file: {:?}", bundle_path))?
-        } else {
-            let bundle_path = PathBuf::from(format!(
-                "Bundles2/{}.bundle.bin",
-                self.index.bundles[file.bundle_index as usize].name
-            ));
-            fetch_bundle_content(
-                self.base_url.as_ref().unwrap(),
-                self.cache_dir.as_ref().unwrap(),
-                &bundle_path,
-            )
-            .with_context(|| format!("Failed to fetch bundle file: {:?}", bundle_path))?
-        };
+        let bundle = self.load_bundle(file.bundle_index)?;
 
         // Pull out the file's contents
-        let content = bundle.read_range(file.offset as usize, file.size as usize);
-        Ok(content)
+        self.read_range(
+            file.bundle_index,
+            &bundle,
+            file.offset as usize,
+            file.size as usize,
+        )
+    }
+}
+
+impl FileSystem for FS {
+    fn list(&self) -> Box<dyn Iterator<Item = String> + '_> {
+        Box::new(FS::list(self))
+    }
+
+    fn read(&self, path: &str) -> Result<Bytes> {
+        FS::read(self, path)
+    }
+
+    fn batch_read<'a>(
+        &'a self,
+        paths: &[&'a str],
+    ) -> Box<dyn Iterator<Item = BatchReadItem<'a>> + 'a> {
+        Box::new(FS::batch_read(self, paths))
     }
 }
@@ -1,6 +1,9 @@
-use std::path::Path;
+use std::{
+    collections::{BTreeMap, HashSet},
+    path::Path,
+};
 
-use anyhow::{anyhow, Context, Result};
+use anyhow::{ensure, Context, Result};
 use bytes::Bytes;
 use nom::{
     bytes::complete::take,
@@ -9,9 +12,16 @@ use nom::{
     number::complete::{le_u32, le_u64},
     IResult,
 };
-use url::Url;
 
-use crate::bundle::{fetch_bundle_content, load_bundle_content, parse_bundle};
+use crate::{
+    bundle::{
+        load_bundle_content, parse_bundle, serialize_bundle, BlockEncoder, Bundle,
+        DEFAULT_BLOCK_GRANULARITY,
+    },
+    hasher::PathHashAlgorithm,
+    parse_error::ParseError,
+    path::{encode_paths, parse_paths},
+};
 
 #[derive(Debug)]
 pub struct BundleInfo {
@@ -65,7 +75,7 @@ fn parse_bundle_info(input: &[u8]) -> IResult<&[u8], BundleInfo> {
 }
 
 // Parser for a vector of Bundles
-fn parse_bundles(input: &[u8]) -> IResult<&[u8], Vec<BundleInfo>> {
+pub(crate) fn parse_bundles(input: &[u8]) -> IResult<&[u8], Vec<BundleInfo>> {
     let (input, bundle_count) = le_u32(input)?;
     count(parse_bundle_info, bundle_count as usize)(input)
 }
@@ -88,7 +98,7 @@ fn parse_file_info(input: &[u8]) -> IResult<&[u8], FileInfo> {
 }
 
 // Parser for a vector of FileInfo
-fn parse_file_infos(input: &[u8]) -> IResult<&[u8], Vec<FileInfo>> {
+pub(crate) fn parse_file_infos(input: &[u8]) -> IResult<&[u8], Vec<FileInfo>> {
     let (input, file_count) = le_u32(input)?;
     count(parse_file_info, file_count as usize)(input)
 }
@@ -111,7 +121,7 @@ fn parse_path_rep(input: &[u8]) -> IResult<&[u8], PathRep> {
 }
 
 // Parser for a vector of PathRep
-fn parse_path_reps(input: &[u8]) -> IResult<&[u8], Vec<PathRep>> {
+pub(crate) fn parse_path_reps(input: &[u8]) -> IResult<&[u8], Vec<PathRep>> {
     let (input, path_count) = le_u32(input)?;
     count(parse_path_rep, path_count as usize)(input)
 }
@@ -121,8 +131,14 @@ pub fn parse_bundle_index(input: &[u8]) -> IResult<&[u8], BundleIndex> {
     let (input, bundles) = parse_bundles(input)?;
     let (input, files) = parse_file_infos(input)?;
     let (input, paths) = parse_path_reps(input)?;
-    let (input, path_rep_bundle) = rest(input)?;
-    let (_, path_rep_bundle) = parse_bundle(path_rep_bundle)?;
+    let (input, path_rep_input) = rest(input)?;
+    let (_, path_rep_bundle) = parse_bundle(path_rep_input)?;
+    let path_rep_bundle = path_rep_bundle.read_all().map_err(|_| {
+        nom::Err::Failure(nom::error::Error::new(
+            path_rep_input,
+            nom::error::ErrorKind::Verify,
+        ))
+    })?;
 
     Ok((
         input,
@@ -130,7 +146,7 @@ pub fn parse_bundle_index(input: &[u8]) -> IResult<&[u8], BundleIndex> {
             bundles,
             files,
             paths,
-            path_rep_bundle: path_rep_bundle.read_all(),
+            path_rep_bundle,
         },
     ))
 }
@@ -139,18 +155,284 @@ pub fn parse_bundle_index(input: &[u8]) -> IResult<&[u8], BundleIndex> {
 pub fn load_index_file(path: &Path) -> Result<BundleIndex> {
     let index_content = load_bundle_content(path)
         .context("Failed to read bundle index")?
-        .read_all();
+        .read_all()
+        .context("Failed to decompress bundle index")?;
     let (_, index) = parse_bundle_index(&index_content)
-        .map_err(|_| anyhow!("Failed to parse bundle as index"))?;
+        .map_err(|e| ParseError::from_nom("bundle index", &index_content, e))?;
     Ok(index)
 }
 
-/// Fetch an index file from the CDN (or cache)
-pub fn fetch_index_file(base_url: &Url, cache_dir: &Path, path: &Path) -> Result<BundleIndex> {
-    let index_content = fetch_bundle_content(base_url, cache_dir, path)
-        .context("Failed to fetch bundle index")?
-        .read_all();
+/// Parse the raw contents of an index file
+pub fn index_from_bytes(bytes: Bytes) -> Result<BundleIndex> {
+    let index_content = Bundle::from_bytes(bytes)
+        .context("Failed to read bundle index")?
+        .read_all()
+        .context("Failed to decompress bundle index")?;
     let (_, index) = parse_bundle_index(&index_content)
-        .map_err(|_| anyhow!("Failed to parse bundle as index"))?;
+        .map_err(|e| ParseError::from_nom("bundle index", &index_content, e))?;
     Ok(index)
 }
+
+/// Work out which hash algorithm an index uses, by checking which one resolves the first path it
+/// lists. Defaults to the current algorithm if nothing resolves.
+pub fn detect_hash_algorithm(index: &BundleIndex) -> PathHashAlgorithm {
+    let Some(path) = index.paths.iter().find_map(|p| {
+        parse_paths(&index.path_rep_bundle, p)
+            .ok()?
+            .get_paths()
+            .into_iter()
+            .next()
+    }) else {
+        return PathHashAlgorithm::default();
+    };
+
+    [PathHashAlgorithm::Murmur64A, PathHashAlgorithm::Fnv1a64]
+        .into_iter()
+        .find(|algorithm| {
+            let hash = algorithm.hash_file(&path);
+            index.files.iter().any(|f| f.hash == hash)
+        })
+        .unwrap_or_default()
+}
+
+/// Serialize an index into the contents of an `_.index.bin` file
+pub fn serialize_bundle_index(index: &BundleIndex, encoder: &impl BlockEncoder) -> Result<Vec<u8>> {
+    let mut bytes = vec![];
+    serialize_index_tables(index, &mut bytes)?;
+
+    // Path reps are stored as a nested bundle
+    bytes.extend(
+        serialize_bundle(&index.path_rep_bundle, DEFAULT_BLOCK_GRANULARITY, encoder)
+            .context("Failed to serialize path reps")?,
+    );
+
+    serialize_bundle(&bytes, DEFAULT_BLOCK_GRANULARITY, encoder)
+}
+
+/// Serialize the bundle, file and path rep tables of an index
+pub(crate) fn serialize_index_tables(index: &BundleIndex, bytes: &mut Vec<u8>) -> Result<()> {
+    bytes.extend(
+        u32::try_from(index.bundles.len())
+            .context("Too many bundles")?
+            .to_le_bytes(),
+    );
+    for bundle in &index.bundles {
+        bytes.extend(
+            u32::try_from(bundle.name.len())
+                .context("Bundle name too long")?
+                .to_le_bytes(),
+        );
+        bytes.extend(bundle.name.as_bytes());
+        bytes.extend(bundle.uncompressed_size.to_le_bytes());
+    }
+
+    bytes.extend(
+        u32::try_from(index.files.len())
+            .context("Too many files")?
+            .to_le_bytes(),
+    );
+    for file in &index.files {
+        bytes.extend(file.hash.to_le_bytes());
+        bytes.extend(file.bundle_index.to_le_bytes());
+        bytes.extend(file.offset.to_le_bytes());
+        bytes.extend(file.size.to_le_bytes());
+    }
+
+    bytes.extend(
+        u32::try_from(index.paths.len())
+            .context("Too many path reps")?
+            .to_le_bytes(),
+    );
+    for path in &index.paths {
+        bytes.extend(path.hash.to_le_bytes());
+        bytes.extend(path.offset.to_le_bytes());
+        bytes.extend(path.size.to_le_bytes());
+        bytes.extend(path.recursive_size.to_le_bytes());
+    }
+
+    Ok(())
+}
+
+/// Builds a bundle index from virtual paths and where they are placed within bundles
+#[derive(Default)]
+pub struct IndexBuilder {
+    bundles: Vec<BundleInfo>,
+    files: Vec<(String, FileInfo)>,
+    hash_algorithm: PathHashAlgorithm,
+}
+
+impl IndexBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Hash paths with the given algorithm. Must be set before adding files.
+    pub fn with_hash_algorithm(mut self, hash_algorithm: PathHashAlgorithm) -> Self {
+        self.hash_algorithm = hash_algorithm;
+        self
+    }
+
+    /// Register a bundle, returning the index to place files into it with
+    pub fn add_bundle(&mut self, name: &str, uncompressed_size: u32) -> u32 {
+        self.bundles.push(BundleInfo {
+            name: name.to_string(),
+            uncompressed_size,
+        });
+        self.bundles.len() as u32 - 1
+    }
+
+    /// Place a file at `offset` within the uncompressed content of a bundle
+    pub fn add_file(&mut self, path: &str, bundle_index: u32, offset: u32, size: u32) {
+        self.files.push((
+            path.to_string(),
+            FileInfo {
+                hash: self.hash_algorithm.hash_file(path),
+                bundle_index,
+                offset,
+                size,
+            },
+        ));
+    }
+
+    /// Validate the placements and assemble the index
+    pub fn build(self) -> Result<BundleIndex> {
+        let mut hashes = HashSet::new();
+        let mut directories = BTreeMap::<&str, Vec<&str>>::new();
+        for (path, file) in &self.files {
+            let bundle = self
+                .bundles
+                .get(file.bundle_index as usize)
+                .with_context(|| format!("Unknown bundle index for {}", path))?;
+            ensure!(
+                file.offset as u64 + file.size as u64 <= bundle.uncompressed_size as u64,
+                "File {} lies outside of bundle {}",
+                path,
+                bundle.name
+            );
+            ensure!(hashes.insert(file.hash), "Duplicate path: {}", path);
+
+            let (directory, leaf) = path.rsplit_once('/').unwrap_or(("", path.as_str()));
+            directories.entry(directory).or_default().push(leaf);
+        }
+
+        // One path rep per directory
+        let mut path_rep_bundle = vec![];
+        let mut paths = vec![];
+        for (directory, leaves) in directories {
+            let chunk = encode_paths(directory, &leaves);
+            let size = u32::try_from(chunk.len()).context("Path rep too large")?;
+            paths.push(PathRep {
+                hash: self.hash_algorithm.hash_directory(directory),
+                offset: u32::try_from(path_rep_bundle.len()).context("Path reps too large")?,
+                size,
+                recursive_size: size,
+            });
+            path_rep_bundle.extend(chunk);
+        }
+
+        Ok(BundleIndex {
+            bundles: self.bundles,
+            files: self.files.into_iter().map(|(_, file)| file).collect(),
+            paths,
+            path_rep_bundle: Bytes::from(path_rep_bundle),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+
+    use super::{detect_hash_algorithm, parse_bundle_index, serialize_bundle_index, IndexBuilder};
+    use crate::{
+        bundle::{parse_bundle, FirstFileEncode, StoredEncoder, MAX_BLOCK_GRANULARITY},
+        hasher::PathHashAlgorithm,
+        path::parse_paths,
+    };
+
+    #[test]
+    fn round_trip_index() {
+        let paths = [
+            "data/mods.datc64",
+            "data/stats.datc64",
+            "art/textures/a.dds",
+            "root.txt",
+        ];
+
+        let mut builder = IndexBuilder::new();
+        let first = builder.add_bundle("Folders/data", 200);
+        let second = builder.add_bundle("Folders/art", 50);
+        builder.add_file(paths[0], first, 0, 100);
+        builder.add_file(paths[1], first, 100, 100);
+        builder.add_file(paths[2], second, 0, 40);
+        builder.add_file(paths[3], second, 40, 10);
+        let index = builder.build().unwrap();
+
+        let bytes = serialize_bundle_index(&index, &StoredEncoder).unwrap();
+        let (_, bundle) = parse_bundle(&bytes).unwrap();
+        let (_, parsed) = parse_bundle_index(&bundle.read_all().unwrap()).unwrap();
+
+        assert_eq!(parsed.bundles.len(), 2);
+        assert_eq!(parsed.bundles[1].name, "Folders/art");
+        assert_eq!(parsed.files.len(), 4);
+        assert_eq!(
+            parsed.files[1].hash,
+            PathHashAlgorithm::Murmur64A.hash_file("DATA/Stats.datc64")
+        );
+        assert_eq!(parsed.files[1].offset, 100);
+
+        let listed = parsed
+            .paths
+            .iter()
+            .flat_map(|p| parse_paths(&parsed.path_rep_bundle, p).unwrap().get_paths())
+            .collect::<HashSet<_>>();
+        assert_eq!(listed, paths.iter().map(|p| p.to_string()).collect());
+    }
+
+    #[test]
+    fn detect_fnv_index() {
+        let mut builder = IndexBuilder::new();
+        let bundle = builder.add_bundle("bundle", 10);
+        builder.add_file("Data/Mods.dat", bundle, 0, 10);
+        assert_eq!(
+            detect_hash_algorithm(&builder.build().unwrap()),
+            PathHashAlgorithm::Murmur64A
+        );
+
+        let mut builder = IndexBuilder::new().with_hash_algorithm(PathHashAlgorithm::Fnv1a64);
+        let bundle = builder.add_bundle("bundle", 10);
+        builder.add_file("Data/Mods.dat", bundle, 0, 10);
+        let index = builder.build().unwrap();
+        assert_eq!(detect_hash_algorithm(&index), PathHashAlgorithm::Fnv1a64);
+        assert_eq!(
+            index.paths[0].hash,
+            PathHashAlgorithm::Fnv1a64.hash_directory("Data")
+        );
+    }
+
+    #[test]
+    fn reject_oversized_path_rep_bundle() {
+        // Found by fuzzing: empty blocks that claim 4 GiB between them, in about 1 KiB of input
+        let block_count = 256u32;
+        let mut input = vec![0; 12]; // No bundles, files or paths
+        input.extend([0; 12]);
+        input.extend((FirstFileEncode::Kraken6 as u32).to_le_bytes());
+        input.extend([0; 4]);
+        input.extend((block_count as u64 * MAX_BLOCK_GRANULARITY as u64).to_le_bytes());
+        input.extend(0u64.to_le_bytes());
+        input.extend(block_count.to_le_bytes());
+        input.extend(MAX_BLOCK_GRANULARITY.to_le_bytes());
+        input.extend([0; 16]);
+        input.extend(vec![0; 4 * block_count as usize]);
+
+        assert!(parse_bundle_index(&input).is_err());
+    }
+
+    #[test]
+    fn reject_out_of_bounds_file() {
+        let mut builder = IndexBuilder::new();
+        let bundle = builder.add_bundle("small", 10);
+        builder.add_file("a.txt", bundle, 5, 10);
+        assert!(builder.build().is_err());
+    }
+}
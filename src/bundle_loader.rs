@@ -1,140 +1,619 @@
 use std::{
-    fs,
+    fs::{self, File, OpenOptions},
     io::{Read, Write},
-    net::TcpStream,
+    net::{TcpStream, ToSocketAddrs},
     path::{Path, PathBuf},
-    time::Duration,
+    sync::Mutex,
+    thread,
+    time::{Duration, Instant},
 };
 
-use anyhow::{bail, Context};
+use anyhow::{bail, ensure, Context};
 use bytes::Bytes;
-use nom::{bytes::complete::take, multi::count, number::complete::le_u8, IResult};
-use reqwest::blocking::Client;
+use nom::{bytes::streaming::take, multi::count, number::streaming::le_u8, IResult};
+use reqwest::{
+    blocking::{Client, Response},
+    header::{CONTENT_RANGE, RANGE},
+    StatusCode,
+};
 use url::Url;
 
+use crate::{
+    bundle::map_file,
+    fs_util::{finish_partial, partial_path, with_suffix},
+    parse_error::ParseError,
+};
+
+/// Caps the combined download rate of everything sharing it
+struct RateLimiter {
+    bytes_per_second: u64,
+    window: Mutex<(Instant, u64)>,
+}
+
+impl RateLimiter {
+    fn new(bytes_per_second: u64) -> Self {
+        Self {
+            bytes_per_second: bytes_per_second.max(1),
+            window: Mutex::new((Instant::now(), 0)),
+        }
+    }
+
+    /// Account for `bytes` having been received, sleeping until the rate is back under the limit
+    fn consume(&self, bytes: u64) {
+        let delay = {
+            let mut window = self.window.lock().unwrap();
+            window.1 += bytes;
+            let due = Duration::from_secs_f64(window.1 as f64 / self.bytes_per_second as f64);
+            let elapsed = window.0.elapsed();
+            if elapsed >= due {
+                // Under the limit, so start a new window rather than letting idle time build up
+                // into a burst
+                *window = (Instant::now(), 0);
+            }
+            due.saturating_sub(elapsed)
+        };
+        thread::sleep(delay);
+    }
+}
+
 pub struct CDNLoader {
-    base_url: Url,
+    base_urls: Vec<Url>,
     cache_dir: String,
+    rate_limiter: Option<RateLimiter>,
 }
 
 impl CDNLoader {
-    pub fn new(base_url: &Url, cache_dir: &str) -> Self {
+    /// Load from the first of `base_urls`, falling back to the others when a download fails
+    pub fn new(base_urls: &[Url], cache_dir: &str) -> Self {
         Self {
-            base_url: base_url.clone(),
+            base_urls: base_urls.to_vec(),
             cache_dir: cache_dir.to_string(),
+            rate_limiter: None,
         }
     }
 
+    /// Cap the combined rate of all downloads, in bytes per second
+    pub fn with_bandwidth_limit(mut self, bytes_per_second: u64) -> Self {
+        self.rate_limiter = Some(RateLimiter::new(bytes_per_second));
+        self
+    }
+
     /// Loads the contents of the bundle file. Either reads from the local cache or from the CDN if
     /// it's not cached.
     pub fn load(&self, path_stub: &Path) -> anyhow::Result<Bytes> {
-        let url = self.base_url.join(
-            path_stub
-                .to_str()
-                .context("Failed to parse path as string")?,
-        )?;
-
-        // If already cached, assume nothing has changed due to version immutability
-        let cache_path =
-            PathBuf::from(&self.cache_dir).join(url.to_string().trim_start_matches("https://"));
-        if let Ok(bytes) = fs::read(&cache_path) {
+        self.load_checked(path_stub, |_| Ok(()))
+    }
+
+    /// Like `load`, but runs `check` over the contents. A cached file that fails is quarantined
+    /// next to where it was and downloaded again, and a freshly downloaded file that fails is
+    /// quarantined and reported as an error.
+    pub fn load_checked(
+        &self,
+        path_stub: &Path,
+        check: impl Fn(&Bytes) -> anyhow::Result<()>,
+    ) -> anyhow::Result<Bytes> {
+        let path_stub = path_stub
+            .to_str()
+            .context("Failed to parse path as string")?;
+        let urls = self
+            .base_urls
+            .iter()
+            .map(|base_url| base_url.join(path_stub))
+            .collect::<Result<Vec<_>, _>>()?;
+        let primary_url = urls.first().context("No CDN URLs to load from")?;
+
+        // If already cached, assume nothing has changed due to version immutability. Files are
+        // cached under the primary URL, whichever one they were downloaded from.
+        let cache_path = PathBuf::from(&self.cache_dir)
+            .join(primary_url.to_string().trim_start_matches("https://"));
+        let mut repairing = false;
+        if let Ok(bytes) = map_file(&cache_path) {
             //eprintln!("Loading bundle from cache: {:?}", path_stub);
-            return Ok(Bytes::from(bytes));
+            match check(&bytes) {
+                Ok(()) => return Ok(bytes),
+                Err(e) => {
+                    // Unmap before moving the file out of the way
+                    drop(bytes);
+                    let quarantine_path = quarantine(&cache_path)?;
+                    eprintln!(
+                        "Cached file is corrupt, moved to {:?} to download again: {:#}",
+                        quarantine_path, e
+                    );
+                    repairing = true;
+                }
+            }
         }
 
-        eprintln!("Downloading bundle: {}", url);
+        let bytes = self.fetch(&urls, &cache_path)?;
+        if let Err(e) = check(&bytes) {
+            drop(bytes);
+            quarantine(&cache_path)?;
+            return Err(e.context(format!("Downloaded file is invalid: {}", urls[0])));
+        }
+        if repairing {
+            eprintln!("Repaired cached file: {:?}", cache_path);
+        }
+        Ok(bytes)
+    }
+
+    /// Download a file into the cache from the first URL that works
+    fn fetch(&self, urls: &[Url], cache_path: &Path) -> anyhow::Result<Bytes> {
         // Short timeout for initial connection, but none for transfer to allow for fetching large
         // files on a poor network connection
         let client = Client::builder()
             .connect_timeout(Duration::from_secs(10))
             .timeout(None)
             .build()?;
-        let bytes = client.get(url).send()?.error_for_status()?.bytes()?;
-        // Save data to file - data first then ETag in case of failure mid-download
+
+        let mut errors = vec![];
+        for url in urls {
+            eprintln!("Downloading bundle: {}", url);
+            match self.download(&client, url, cache_path) {
+                Ok(()) => return map_file(cache_path),
+                Err(e) => {
+                    eprintln!("Failed to download {}: {:#}", url, e);
+                    errors.push(e);
+                }
+            }
+        }
+
+        Err(errors.pop().context("No CDN URLs to load from")?)
+    }
+
+    /// Download a file to `cache_path` in chunks, keeping to the bandwidth limit. Data goes to a
+    /// `.part` file that's only renamed into place once complete, so an interrupted download never
+    /// leaves a truncated file in the cache. The expected length is recorded alongside, and the
+    /// next attempt resumes from where the last one stopped.
+    fn download(&self, client: &Client, url: &Url, cache_path: &Path) -> anyhow::Result<()> {
+        let part_path = partial_path(cache_path);
+        let length_path = with_suffix(cache_path, ".part.len");
         fs::create_dir_all(cache_path.parent().context("Failed to get path parent")?)?;
-        fs::write(&cache_path, &bytes)?;
 
-        Ok(bytes)
+        // Only resume when the length is known, otherwise a stale partial file can't be detected
+        let expected_length = fs::read_to_string(&length_path)
+            .ok()
+            .and_then(|length| length.trim().parse::<u64>().ok());
+        let existing = match expected_length {
+            Some(_) => fs::metadata(&part_path).map_or(0, |m| m.len()),
+            None => 0,
+        };
+
+        let mut request = client.get(url.clone());
+        if existing > 0 {
+            request = request.header(RANGE, format!("bytes={}-", existing));
+        }
+        let response = request.send()?;
+        if existing > 0 && response.status() == StatusCode::RANGE_NOT_SATISFIABLE {
+            eprintln!("Partial download is invalid, restarting: {}", url);
+            fs::remove_file(&part_path)?;
+            fs::remove_file(&length_path)?;
+            return self.download(client, url, cache_path);
+        }
+        let mut response = response.error_for_status()?;
+
+        let resumed = existing > 0 && response.status() == StatusCode::PARTIAL_CONTENT;
+        if resumed && content_range(&response) != expected_length.map(|total| (existing, total)) {
+            // The file on the server isn't the one that was partially downloaded
+            eprintln!("Partial download doesn't match, restarting: {}", url);
+            fs::remove_file(&part_path)?;
+            fs::remove_file(&length_path)?;
+            return self.download(client, url, cache_path);
+        }
+
+        let (mut file, mut written, expected_length) = if resumed {
+            eprintln!("Resuming download from byte {}: {}", existing, url);
+            let file = OpenOptions::new().append(true).open(&part_path)?;
+            (file, existing, expected_length)
+        } else {
+            // Record the length before any data, so an interrupted download knows what to resume
+            let expected_length = response.content_length();
+            match expected_length {
+                Some(length) => fs::write(&length_path, length.to_string())?,
+                None => {
+                    let _ = fs::remove_file(&length_path);
+                }
+            }
+            (File::create(&part_path)?, 0, expected_length)
+        };
+
+        let mut chunk = vec![0; 64 * 1024];
+        loop {
+            let read = response.read(&mut chunk)?;
+            if read == 0 {
+                break;
+            }
+            file.write_all(&chunk[..read])?;
+            written += read as u64;
+            if let Some(rate_limiter) = &self.rate_limiter {
+                rate_limiter.consume(read as u64);
+            }
+        }
+        file.sync_all()?;
+
+        if let Some(expected_length) = expected_length {
+            ensure!(
+                written == expected_length,
+                "Download ended after {} of {} bytes",
+                written,
+                expected_length
+            );
+        }
+
+        finish_partial(cache_path)?;
+        let _ = fs::remove_file(&length_path);
+        Ok(())
     }
 }
 
-pub fn cdn_base_url(cache_dir: &Path, version: &str) -> anyhow::Result<Url> {
-    // Check cache for version URL
+/// Move a bad cache entry aside as `.corrupt`, replacing any earlier one, so it can be inspected
+/// but is never loaded again
+fn quarantine(cache_path: &Path) -> anyhow::Result<PathBuf> {
+    let quarantine_path = with_suffix(cache_path, ".corrupt");
+    fs::rename(cache_path, &quarantine_path)
+        .with_context(|| format!("Failed to quarantine {:?}", cache_path))?;
+    Ok(quarantine_path)
+}
+
+/// Start offset and total length from a partial response's `Content-Range` header
+fn content_range(response: &Response) -> Option<(u64, u64)> {
+    let header = response.headers().get(CONTENT_RANGE)?.to_str().ok()?;
+    let (range, total) = header.strip_prefix("bytes ")?.split_once('/')?;
+    let (start, _) = range.split_once('-')?;
+    Some((start.parse().ok()?, total.parse().ok()?))
+}
+
+/// A game's patch server, and the request that asks it for the live CDN URLs
+#[derive(Debug, Clone, Copy)]
+pub struct PatchServer {
+    pub host: &'static str,
+    pub request: &'static [u8],
+}
+
+pub const POE1_PATCH_SERVER: PatchServer = PatchServer {
+    host: "patch.pathofexile.com:12995",
+    request: &[1, 6],
+};
+
+pub const POE2_PATCH_SERVER: PatchServer = PatchServer {
+    host: "patch.pathofexile2.com:13060",
+    request: &[1, 7],
+};
+
+/// What a patch server reports about the live version of its game
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PatchInfo {
+    /// Version string, as found at the end of the CDN URLs
+    pub version: String,
+    /// CDN URLs in order of preference
+    pub urls: Vec<Url>,
+}
+
+/// Base URLs of the CDN for a version, either `1` or `2` for the live version of each game, or a
+/// specific patch such as `3.25.3.2`
+pub fn cdn_base_urls(cache_dir: &Path, version: &str) -> anyhow::Result<Vec<Url>> {
+    // Check cache for version URLs
     let cache_dir = cache_dir.join("cdn_url");
     let cache_file = cache_dir.join(version);
 
-    // If we have a recently cached version, just use that instead
+    // If we have recently cached URLs, just use them instead
     if cache_file.exists() && fs::metadata(&cache_file)?.modified()?.elapsed()?.as_secs() < 3600 {
-        let url = Url::parse(fs::read_to_string(&cache_file)?.as_str())
-            .with_context(|| "Failed to parse URL")?;
-        eprintln!("Using cached CDN URL: {}", url);
-        return Ok(url);
+        let urls = fs::read_to_string(&cache_file)?
+            .lines()
+            .map(Url::parse)
+            .collect::<Result<Vec<_>, _>>()
+            .context("Failed to parse cached URL")?;
+        if !urls.is_empty() {
+            eprintln!("Using cached CDN URL: {}", urls[0]);
+            return Ok(urls);
+        }
     }
 
-    let url = match version {
+    let urls = match version {
         // Latest PoE 1
-        "1" => cur_url("patch.pathofexile.com:12995".to_string(), &[1, 6]),
+        "1" => fetch_patch_info(&POE1_PATCH_SERVER)?.urls,
         // Latest PoE 2
-        "2" => cur_url("patch.pathofexile2.com:13060".to_string(), &[1, 7]),
+        "2" => fetch_patch_info(&POE2_PATCH_SERVER)?.urls,
         // Specific PoE 1 patch
-        v if v.starts_with("3.") => Url::parse(format!("https://patch.poecdn.com/{}/", v).as_str())
-            .with_context(|| "Failed to parse URL"),
+        v if v.starts_with("3.") => {
+            vec![Url::parse(&format!("https://patch.poecdn.com/{}/", v))
+                .with_context(|| format!("Failed to get URL for version: {}", v))?]
+        }
         // Specific PoE 2 patch
         v if v.starts_with("4.") => {
-            Url::parse(format!("https://patch-poe2.poecdn.com/{}/", v).as_str())
-                .with_context(|| "Failed to parse URL")
+            vec![Url::parse(&format!("https://patch-poe2.poecdn.com/{}/", v))
+                .with_context(|| format!("Failed to get URL for version: {}", v))?]
         }
         // Invalid patch
-        _ => panic!("Invalid version provided"),
-    }
-    .unwrap_or_else(|_| panic!("Failed to get URL for version: {}", version));
+        v => bail!("Invalid version provided: {}", v),
+    };
 
+    let contents = urls.iter().map(|u| format!("{}\n", u)).collect::<String>();
     fs::create_dir_all(&cache_dir).context("Failed to create cache directory")?;
-    fs::write(&cache_file, url.as_str()).context("Failed to write URL to cache")?;
-    eprintln!("Refreshed CDN URL: {}", url);
-    Ok(url)
+    fs::write(&cache_file, contents).context("Failed to write URL to cache")?;
+    eprintln!("Refreshed CDN URL: {}", urls[0]);
+    Ok(urls)
 }
 
+// Parser for the patch server's response, a count of strings and some padding followed by the
+// strings themselves. Streaming, so a partial response asks for more data.
 fn parse_response(input: &[u8]) -> IResult<&[u8], Vec<String>> {
     let (input, num_strings) = le_u8(input)?; // Parse the number of strings (N)
     let (input, _) = take(33usize)(input)?; // Discard the next 33 bytes (padding)
     count(parse_utf16_string, num_strings as usize)(input) // Parse N strings
 }
 
+// Parser for a UTF-16 string prefixed with its length in code units
 fn parse_utf16_string(input: &[u8]) -> IResult<&[u8], String> {
-    let (input, len) = le_u8(input)?; // Parse string length (L)
-    let (input, utf16_bytes) = take(len as usize * 2)(input)?; // Extract L * 2 bytes of UTF-16 data
+    let (rest, len) = le_u8(input)?; // Parse string length (L)
+    let (rest, utf16_bytes) = take(len as usize * 2)(rest)?; // Extract L * 2 bytes of UTF-16 data
     let utf16_words: Vec<u16> = utf16_bytes
         .chunks_exact(2)
         .map(|chunk| u16::from_le_bytes([chunk[0], chunk[1]]))
         .collect();
-    let string =
-        String::from_utf16(&utf16_words).expect("Failed to parse response as UTF-16 string");
-
-    Ok((input, string))
-}
-
-/// Fetch the current latest version of the game
-fn cur_url(host: String, send: &[u8]) -> anyhow::Result<Url> {
-    // Fetch data from the CDN - todo: looks like this returns a list of URLs. Might need to use a
-    // streaming-style parsing instead of just reading 1Kb down the line if there's a bunch of
-    // strings
-    let mut stream = TcpStream::connect(host)?;
-    stream.write_all(send)?;
-    let mut buf = [0; 1024];
-    let read = stream.read(&mut buf)?;
-
-    // Parse the response
-    let strings = if let Ok((_, strings)) = parse_response(&buf[..read]) {
-        strings
-    } else {
-        bail!("Failed to parse URLs from CDN")
-    };
+    let string = String::from_utf16(&utf16_words).map_err(|_| {
+        nom::Err::Failure(nom::error::Error::new(input, nom::error::ErrorKind::Verify))
+    })?;
+
+    Ok((rest, string))
+}
+
+/// Read a complete response from the patch server, parsing it as data arrives
+fn read_response(reader: &mut impl Read) -> anyhow::Result<Vec<String>> {
+    let mut buf = vec![];
+    let mut chunk = [0; 1024];
+    loop {
+        match parse_response(&buf) {
+            Ok((_, strings)) => return Ok(strings),
+            Err(nom::Err::Incomplete(_)) => {}
+            Err(e) => return Err(ParseError::from_nom("patch server response", &buf, e).into()),
+        }
 
-    // Grab the first one and return it as a URL
-    strings
+        let read = reader
+            .read(&mut chunk)
+            .context("Failed to read from patch server")?;
+        ensure!(
+            read > 0,
+            ParseError::new(
+                "patch server response",
+                buf.len(),
+                "Connection closed before the response was complete"
+            )
+        );
+        buf.extend_from_slice(&chunk[..read]);
+    }
+}
+
+/// Turn the strings from a patch server response into CDN URLs and the version they serve
+fn patch_info_from_strings(strings: Vec<String>) -> anyhow::Result<PatchInfo> {
+    let urls = strings
         .into_iter()
-        .map(|s| Url::parse(&s).expect("Failed to parse URL"))
+        .filter(|s| !s.is_empty())
+        .filter_map(|s| match Url::parse(&s) {
+            Ok(url) => Some(url),
+            Err(e) => {
+                eprintln!("Ignoring invalid URL from patch server {:?}: {}", s, e);
+                None
+            }
+        })
+        .collect::<Vec<_>>();
+
+    let version = urls
+        .first()
+        .context("No URLs returned from patch server")?
+        .path_segments()
+        .and_then(|mut segments| segments.rfind(|s| !s.is_empty()))
+        .context("No version in patch server URL")?
+        .to_string();
+
+    Ok(PatchInfo { version, urls })
+}
+
+/// Ask a patch server for the live version of its game
+pub fn fetch_patch_info(server: &PatchServer) -> anyhow::Result<PatchInfo> {
+    query_patch_server(server.host, server.request)
+}
+
+fn query_patch_server(host: &str, request: &[u8]) -> anyhow::Result<PatchInfo> {
+    let address = host
+        .to_socket_addrs()
+        .with_context(|| format!("Failed to resolve {}", host))?
         .next()
-        .with_context(|| "No URLs returned from CDN")
+        .with_context(|| format!("No addresses for {}", host))?;
+    let mut stream = TcpStream::connect_timeout(&address, Duration::from_secs(10))
+        .with_context(|| format!("Failed to connect to {}", host))?;
+    stream.set_read_timeout(Some(Duration::from_secs(10)))?;
+    stream
+        .write_all(request)
+        .context("Failed to send request to patch server")?;
+
+    patch_info_from_strings(read_response(&mut stream)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        fs,
+        io::{Read, Write},
+        net::TcpListener,
+        path::Path,
+        thread,
+    };
+
+    use anyhow::ensure;
+    use reqwest::blocking::Client;
+    use url::Url;
+
+    use super::{query_patch_server, CDNLoader};
+    use crate::{
+        bundle_source::{BundleSource, CdnSource},
+        fs_util::{partial_path, with_suffix},
+        test_support::TestBundles,
+    };
+
+    fn utf16_string(s: &str) -> Vec<u8> {
+        let units = s.encode_utf16().collect::<Vec<_>>();
+        let mut bytes = vec![units.len() as u8];
+        bytes.extend(units.iter().flat_map(|u| u.to_le_bytes()));
+        bytes
+    }
+
+    #[test]
+    fn query_local_patch_server() {
+        let mut response = vec![3];
+        response.extend([0; 33]);
+        response.extend(utf16_string("https://patch.poecdn.com/3.25.3.2/"));
+        response.extend(utf16_string("not a url"));
+        response.extend(utf16_string("https://backup.poecdn.com/3.25.3.2/"));
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let host = listener.local_addr().unwrap().to_string();
+        let server = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut request = [0; 2];
+            stream.read_exact(&mut request).unwrap();
+            assert_eq!(request, [1, 6]);
+
+            // Dribble the response out to exercise the streaming parser
+            for chunk in response.chunks(7) {
+                stream.write_all(chunk).unwrap();
+                stream.flush().unwrap();
+            }
+        });
+
+        let info = query_patch_server(&host, &[1, 6]).unwrap();
+        server.join().unwrap();
+
+        assert_eq!(info.version, "3.25.3.2");
+        assert_eq!(info.urls.len(), 2);
+        assert_eq!(info.urls[1].host_str(), Some("backup.poecdn.com"));
+    }
+
+    #[test]
+    fn reject_truncated_response() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let host = listener.local_addr().unwrap().to_string();
+        let server = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut request = [0; 2];
+            stream.read_exact(&mut request).unwrap();
+            stream.write_all(&[2, 0, 0]).unwrap();
+        });
+
+        assert!(query_patch_server(&host, &[1, 7]).is_err());
+        server.join().unwrap();
+    }
+
+    /// Answer a single HTTP request on a local port, returning its address and the request
+    fn serve_http(
+        response: impl AsRef<[u8]> + Send + 'static,
+    ) -> (String, thread::JoinHandle<String>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        let server = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut request = vec![];
+            let mut buf = [0; 1024];
+            while !request.ends_with(b"\r\n\r\n") {
+                let read = stream.read(&mut buf).unwrap();
+                assert!(read > 0, "Connection closed mid-request");
+                request.extend_from_slice(&buf[..read]);
+            }
+            stream.write_all(response.as_ref()).unwrap();
+            String::from_utf8(request).unwrap().to_lowercase()
+        });
+        (address, server)
+    }
+
+    #[test]
+    fn resume_partial_download() {
+        let cache_dir =
+            std::env::temp_dir().join(format!("poe_tools_bundle_loader_{}", std::process::id()));
+        fs::create_dir_all(&cache_dir).unwrap();
+        let cache_path = cache_dir.join("test.bundle.bin");
+        fs::write(partial_path(&cache_path), "hello ").unwrap();
+        fs::write(with_suffix(&cache_path, ".part.len"), "11").unwrap();
+
+        let (address, server) = serve_http(
+            b"HTTP/1.1 206 Partial Content\r\n\
+            Content-Range: bytes 6-10/11\r\n\
+            Content-Length: 5\r\n\
+            Connection: close\r\n\r\n\
+            world",
+        );
+        let url = Url::parse(&format!("http://{}/test.bundle.bin", address)).unwrap();
+        let loader = CDNLoader::new(&[url.clone()], cache_dir.to_str().unwrap());
+        loader.download(&Client::new(), &url, &cache_path).unwrap();
+        assert!(server.join().unwrap().contains("range: bytes=6-"));
+
+        assert_eq!(fs::read_to_string(&cache_path).unwrap(), "hello world");
+        assert!(!partial_path(&cache_path).exists());
+        assert!(!with_suffix(&cache_path, ".part.len").exists());
+
+        fs::remove_dir_all(&cache_dir).unwrap();
+    }
+
+    #[test]
+    fn repair_corrupt_cache_entry() {
+        let cache_dir =
+            std::env::temp_dir().join(format!("poe_tools_cache_repair_{}", std::process::id()));
+
+        let (address, server) = serve_http(
+            b"HTTP/1.1 200 OK\r\n\
+            Content-Length: 4\r\n\
+            Connection: close\r\n\r\n\
+            good",
+        );
+        let base_url = Url::parse(&format!("http://{}/", address)).unwrap();
+        let cache_path = cache_dir.join(base_url.join("test.bin").unwrap().to_string());
+        fs::create_dir_all(cache_path.parent().unwrap()).unwrap();
+        fs::write(&cache_path, "bad").unwrap();
+
+        let loader = CDNLoader::new(&[base_url], cache_dir.to_str().unwrap());
+        let bytes = loader
+            .load_checked(Path::new("test.bin"), |bytes| {
+                ensure!(bytes.as_ref() == b"good", "Not good");
+                Ok(())
+            })
+            .unwrap();
+        server.join().unwrap();
+
+        assert_eq!(bytes, "good");
+        assert_eq!(fs::read_to_string(&cache_path).unwrap(), "good");
+        assert_eq!(
+            fs::read_to_string(with_suffix(&cache_path, ".corrupt")).unwrap(),
+            "bad"
+        );
+
+        fs::remove_dir_all(&cache_dir).unwrap();
+    }
+
+    #[test]
+    fn repair_corrupt_cached_index() {
+        let cache_dir =
+            std::env::temp_dir().join(format!("poe_tools_index_repair_{}", std::process::id()));
+
+        let index = TestBundles::new()
+            .bundle("bundle", b"data", &[("a.txt", 0, 4)])
+            .index_bytes();
+        let mut response = format!(
+            "HTTP/1.1 200 OK\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+            index.len()
+        )
+        .into_bytes();
+        response.extend_from_slice(&index);
+        let (address, server) = serve_http(response);
+        let base_url = Url::parse(&format!("http://{}/", address)).unwrap();
+        let cache_path = cache_dir.join(base_url.join("Bundles2/_.index.bin").unwrap().to_string());
+        fs::create_dir_all(cache_path.parent().unwrap()).unwrap();
+        fs::write(&cache_path, &index[..index.len() - 1]).unwrap();
+
+        let source = CdnSource::new(&[base_url], &cache_dir).unwrap();
+        assert_eq!(source.load_index().unwrap(), index);
+        server.join().unwrap();
+
+        assert_eq!(fs::read(&cache_path).unwrap(), index);
+        assert!(with_suffix(&cache_path, ".corrupt").exists());
+
+        fs::remove_dir_all(&cache_dir).unwrap();
+    }
 }
@@ -0,0 +1,203 @@
+use std::path::{Path, PathBuf};
+
+use anyhow::{ensure, Context, Result};
+use bytes::Bytes;
+use url::Url;
+
+use crate::{
+    bundle::{map_file, Bundle},
+    bundle_index::BundleInfo,
+    bundle_loader::CDNLoader,
+    ggpk::GGPK,
+};
+
+/// Somewhere the index and bundle files can be loaded from
+pub trait BundleSource: Send + Sync {
+    /// Load the raw contents of `_.index.bin`
+    fn load_index(&self) -> Result<Bytes>;
+
+    /// Load the raw contents of a bundle file
+    fn load_bundle(&self, bundle: &BundleInfo) -> Result<Bytes>;
+}
+
+/// Loose files in a folder laid out like `Bundles2/`
+pub struct LocalSource {
+    bundles_folder: PathBuf,
+}
+
+impl LocalSource {
+    pub fn new(bundles_folder: PathBuf) -> Self {
+        Self { bundles_folder }
+    }
+}
+
+impl BundleSource for LocalSource {
+    fn load_index(&self) -> Result<Bytes> {
+        let index_path = self.bundles_folder.join("_.index.bin");
+        map_file(&index_path).with_context(|| format!("Failed to load index: {:?}", index_path))
+    }
+
+    fn load_bundle(&self, bundle: &BundleInfo) -> Result<Bytes> {
+        let bundle_path = self
+            .bundles_folder
+            .join(format!("{}.bundle.bin", bundle.name));
+        map_file(&bundle_path)
+            .with_context(|| format!("Failed to load bundle file: {:?}", bundle_path))
+    }
+}
+
+/// A Steam install of the game
+pub struct SteamSource {
+    bundles: LocalSource,
+}
+
+impl SteamSource {
+    pub fn new(steam_folder: &Path) -> Self {
+        Self {
+            bundles: LocalSource::new(steam_folder.join("Bundles2")),
+        }
+    }
+}
+
+impl BundleSource for SteamSource {
+    fn load_index(&self) -> Result<Bytes> {
+        self.bundles.load_index()
+    }
+
+    fn load_bundle(&self, bundle: &BundleInfo) -> Result<Bytes> {
+        self.bundles.load_bundle(bundle)
+    }
+}
+
+/// The patch CDN, with downloads cached locally
+pub struct CdnSource {
+    loader: CDNLoader,
+}
+
+impl CdnSource {
+    pub fn new(base_urls: &[Url], cache_dir: &Path) -> Result<Self> {
+        let cache_dir = cache_dir.to_str().context("Invalid cache directory")?;
+        Ok(Self {
+            loader: CDNLoader::new(base_urls, cache_dir),
+        })
+    }
+
+    /// Cap the combined rate of all downloads, in bytes per second
+    pub fn with_bandwidth_limit(mut self, bytes_per_second: u64) -> Self {
+        self.loader = self.loader.with_bandwidth_limit(bytes_per_second);
+        self
+    }
+}
+
+impl BundleSource for CdnSource {
+    /// Load the index, replacing a cached copy that is truncated or corrupt
+    fn load_index(&self) -> Result<Bytes> {
+        self.loader
+            .load_checked(Path::new("Bundles2/_.index.bin"), check_index)
+            .context("Failed to fetch index")
+    }
+
+    /// Load a bundle, replacing cached copies that are truncated or don't match the index
+    fn load_bundle(&self, bundle: &BundleInfo) -> Result<Bytes> {
+        let bundle_path = PathBuf::from(format!("Bundles2/{}.bundle.bin", bundle.name));
+        self.loader
+            .load_checked(&bundle_path, |bytes| check_bundle(bytes, bundle))
+            .with_context(|| format!("Failed to fetch bundle file: {:?}", bundle_path))
+    }
+}
+
+/// Check a bundle file is complete and matches what the index expects, without decompressing it
+fn check_bundle(bytes: &Bytes, bundle: &BundleInfo) -> Result<()> {
+    let parsed = Bundle::from_bytes(bytes.clone())?;
+    ensure!(
+        parsed.head.uncompressed_size == bundle.uncompressed_size as u64,
+        "Bundle header says {} bytes uncompressed, index expects {}",
+        parsed.head.uncompressed_size,
+        bundle.uncompressed_size
+    );
+    parsed.check_range(0, bundle.uncompressed_size as usize)
+}
+
+/// Check an index file is a complete bundle, without decompressing it
+fn check_index(bytes: &Bytes) -> Result<()> {
+    let parsed = Bundle::from_bytes(bytes.clone())?;
+    parsed.check_range(0, parsed.head.uncompressed_size as usize)
+}
+
+/// Bundles stored inside a standalone client's `Content.ggpk`
+pub struct GgpkSource {
+    ggpk: GGPK,
+}
+
+impl GgpkSource {
+    pub fn new(ggpk: GGPK) -> Self {
+        Self { ggpk }
+    }
+}
+
+impl BundleSource for GgpkSource {
+    fn load_index(&self) -> Result<Bytes> {
+        self.ggpk.read("Bundles2/_.index.bin")
+    }
+
+    fn load_bundle(&self, bundle: &BundleInfo) -> Result<Bytes> {
+        let bundle_path = format!("Bundles2/{}.bundle.bin", bundle.name);
+        self.ggpk
+            .read(&bundle_path)
+            .with_context(|| format!("Failed to load bundle file: {:?}", bundle_path))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bytes::Bytes;
+
+    use crate::test_support::TestBundles;
+
+    #[test]
+    fn read_from_custom_source() {
+        let fs = TestBundles::new()
+            .bundle(
+                "first",
+                b"1111222222",
+                &[("a/one.txt", 0, 4), ("a/two.txt", 4, 6)],
+            )
+            .bundle("second", b"333", &[("b/three.txt", 0, 3)])
+            .fs();
+
+        assert_eq!(fs.read("a/two.txt").unwrap(), "222222");
+
+        let paths = ["a/one.txt", "b/three.txt", "c/unknown.txt"];
+        assert_eq!(fs.bundles_for(&paths), [0, 1]);
+        let mut results = fs
+            .batch_read(&paths)
+            .map(|r| r.map_err(|(path, _)| path))
+            .collect::<Vec<_>>();
+        results.sort_by_key(|r| match r {
+            Ok((path, _)) => *path,
+            Err(path) => *path,
+        });
+        assert_eq!(
+            results,
+            [
+                Ok(("a/one.txt", Bytes::from("1111"))),
+                Ok(("b/three.txt", Bytes::from("333"))),
+                Err("c/unknown.txt"),
+            ]
+        );
+    }
+
+    #[test]
+    fn verify_bundles() {
+        let fs = TestBundles::new()
+            .bundle("good", b"abcdef", &[("good.txt", 0, 6)])
+            .bundle_sized("short", 6, b"abc", &[("short.txt", 0, 6)])
+            .missing_bundle("missing", 1)
+            .fs();
+
+        assert!(fs.verify_bundle(0).is_ok());
+        assert!(fs.verify_bundle(1).is_err());
+        assert!(fs.verify_bundle(2).is_err());
+        assert!(fs.verify_bundle(3).is_err());
+    }
+}
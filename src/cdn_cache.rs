@@ -0,0 +1,232 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime},
+};
+
+use anyhow::{Context, Result};
+
+use crate::index_cache::IndexCache;
+
+/// Folders in the cache directory that hold something other than CDN downloads
+pub const SUPPORT_FOLDERS: [&str; 3] = ["cdn_url", "index", "schema"];
+
+/// Size of everything under a folder
+#[derive(Debug, Clone, Copy)]
+pub struct FolderStats {
+    pub size: u64,
+    pub files: usize,
+    /// Most recent modification of any file, i.e. the last time anything was downloaded into it
+    pub modified: SystemTime,
+}
+
+/// One patch version's downloads, stored under `<cache_dir>/<host>/<version>/`
+#[derive(Debug, Clone)]
+pub struct CachedVersion {
+    pub host: String,
+    pub version: String,
+    pub path: PathBuf,
+    pub stats: FolderStats,
+}
+
+/// Which cached versions to remove. Each limit that's set applies on its own.
+#[derive(Debug, Clone, Default)]
+pub struct PrunePolicy {
+    /// Remove versions not downloaded into for this long
+    pub max_age: Option<Duration>,
+    /// Keep only this many of the most recently downloaded versions
+    pub keep: Option<usize>,
+    /// Remove the oldest versions until the rest fit in this many bytes
+    pub max_size: Option<u64>,
+}
+
+/// Total up the files under a folder, recursively
+pub fn folder_stats(path: &Path) -> Result<FolderStats> {
+    let mut stats = FolderStats {
+        size: 0,
+        files: 0,
+        modified: SystemTime::UNIX_EPOCH,
+    };
+
+    for entry in fs::read_dir(path).with_context(|| format!("Failed to read {:?}", path))? {
+        let entry = entry?;
+        let metadata = entry.metadata()?;
+        if metadata.is_dir() {
+            let inner = folder_stats(&entry.path())?;
+            stats.size += inner.size;
+            stats.files += inner.files;
+            stats.modified = stats.modified.max(inner.modified);
+        } else {
+            stats.size += metadata.len();
+            stats.files += 1;
+            stats.modified = stats.modified.max(metadata.modified()?);
+        }
+    }
+
+    Ok(stats)
+}
+
+/// Every patch version with downloads in the cache, most recently downloaded first
+pub fn cached_versions(cache_dir: &Path) -> Result<Vec<CachedVersion>> {
+    if !cache_dir.exists() {
+        return Ok(vec![]);
+    }
+
+    let mut versions = vec![];
+    for host in
+        fs::read_dir(cache_dir).with_context(|| format!("Failed to read {:?}", cache_dir))?
+    {
+        let host = host?;
+        let host_name = host.file_name().to_string_lossy().to_string();
+        if !host.file_type()?.is_dir() || SUPPORT_FOLDERS.contains(&host_name.as_str()) {
+            continue;
+        }
+
+        for version in fs::read_dir(host.path())? {
+            let version = version?;
+            if !version.file_type()?.is_dir() {
+                continue;
+            }
+            versions.push(CachedVersion {
+                host: host_name.clone(),
+                version: version.file_name().to_string_lossy().to_string(),
+                path: version.path(),
+                stats: folder_stats(&version.path())?,
+            });
+        }
+    }
+
+    versions.sort_by(|a, b| b.stats.modified.cmp(&a.stats.modified));
+    Ok(versions)
+}
+
+/// Pick which versions a policy removes. `versions` must be most recently downloaded first, as
+/// returned by `cached_versions`.
+pub fn select_for_pruning<'a>(
+    versions: &'a [CachedVersion],
+    policy: &PrunePolicy,
+    now: SystemTime,
+) -> Vec<&'a CachedVersion> {
+    let mut remove = vec![false; versions.len()];
+    let mut remaining_size = versions.iter().map(|v| v.stats.size).sum::<u64>();
+
+    for (i, version) in versions.iter().enumerate() {
+        let too_many = policy.keep.is_some_and(|keep| i >= keep);
+        let too_old = policy.max_age.is_some_and(|max_age| {
+            now.duration_since(version.stats.modified)
+                .is_ok_and(|age| age > max_age)
+        });
+        if too_many || too_old {
+            remove[i] = true;
+            remaining_size -= version.stats.size;
+        }
+    }
+
+    // Oldest go first when over the size cap
+    if let Some(max_size) = policy.max_size {
+        for (i, version) in versions.iter().enumerate().rev() {
+            if remaining_size <= max_size {
+                break;
+            }
+            if !remove[i] {
+                remove[i] = true;
+                remaining_size -= version.stats.size;
+            }
+        }
+    }
+
+    versions
+        .iter()
+        .zip(remove)
+        .filter(|(_, remove)| *remove)
+        .map(|(version, _)| version)
+        .collect()
+}
+
+/// Parsed indexes that none of `versions` use, such as those left behind by older Steam or GGPK
+/// patches, along with their sizes
+pub fn unused_index_entries(
+    cache_dir: &Path,
+    versions: &[&CachedVersion],
+) -> Result<Vec<(PathBuf, u64)>> {
+    let raw_indexes = versions
+        .iter()
+        .filter_map(|version| fs::read(version.path.join("Bundles2/_.index.bin")).ok())
+        .collect::<Vec<_>>();
+    IndexCache::new(cache_dir).unused_entries(&raw_indexes)
+}
+
+/// Delete a cached version, along with the parsed copy of its index
+pub fn remove_version(cache_dir: &Path, version: &CachedVersion) -> Result<()> {
+    if let Ok(raw_index) = fs::read(version.path.join("Bundles2/_.index.bin")) {
+        IndexCache::new(cache_dir).remove(&raw_index)?;
+    }
+
+    fs::remove_dir_all(&version.path)
+        .with_context(|| format!("Failed to remove {:?}", version.path))?;
+
+    // Tidy up the host folder once its last version is gone, which fails harmlessly otherwise
+    if let Some(host_folder) = version.path.parent() {
+        let _ = fs::remove_dir(host_folder);
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        path::PathBuf,
+        time::{Duration, SystemTime},
+    };
+
+    use super::{select_for_pruning, CachedVersion, FolderStats, PrunePolicy};
+
+    #[test]
+    fn prune_versions() {
+        let now = SystemTime::now();
+        let day = Duration::from_secs(24 * 60 * 60);
+        let versions = (0..4)
+            .map(|i| CachedVersion {
+                host: "patch.poecdn.com".to_string(),
+                version: format!("3.25.{}", 4 - i),
+                path: PathBuf::new(),
+                stats: FolderStats {
+                    size: 100,
+                    files: 1,
+                    modified: now - day * i,
+                },
+            })
+            .collect::<Vec<_>>();
+        let pruned = |policy: PrunePolicy| {
+            select_for_pruning(&versions, &policy, now)
+                .iter()
+                .map(|v| v.version.as_str())
+                .collect::<Vec<_>>()
+        };
+
+        assert!(pruned(PrunePolicy::default()).is_empty());
+        assert_eq!(
+            pruned(PrunePolicy {
+                keep: Some(3),
+                ..Default::default()
+            }),
+            ["3.25.1"]
+        );
+        assert_eq!(
+            pruned(PrunePolicy {
+                max_age: Some(day + day / 2),
+                ..Default::default()
+            }),
+            ["3.25.2", "3.25.1"]
+        );
+        assert_eq!(
+            pruned(PrunePolicy {
+                keep: Some(3),
+                max_size: Some(150),
+                ..Default::default()
+            }),
+            ["3.25.3", "3.25.2", "3.25.1"]
+        );
+    }
+}
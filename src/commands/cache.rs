@@ -0,0 +1,145 @@
+use std::{
+    fs,
+    io::{self, BufWriter, Write},
+    path::Path,
+    time::SystemTime,
+};
+
+use anyhow::{bail, Context, Result};
+
+use crate::cdn_cache::{
+    cached_versions, folder_stats, remove_version, select_for_pruning, unused_index_entries,
+    PrunePolicy, SUPPORT_FOLDERS,
+};
+
+/// Render a byte count for people to read
+fn format_size(bytes: u64) -> String {
+    const UNITS: [&str; 4] = ["KiB", "MiB", "GiB", "TiB"];
+    if bytes < 1024 {
+        return format!("{} B", bytes);
+    }
+
+    let mut size = bytes as f64 / 1024.0;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+    format!("{:.1} {}", size, UNITS[unit])
+}
+
+/// Days since a time, for display
+fn age_in_days(time: SystemTime) -> u64 {
+    time.elapsed()
+        .map_or(0, |age| age.as_secs() / (24 * 60 * 60))
+}
+
+/// Print each cached patch version as `host version size files age`, newest download first
+pub fn list_cache(cache_dir: &Path) -> Result<()> {
+    let mut stdout = BufWriter::new(io::stdout().lock());
+
+    for version in cached_versions(cache_dir)? {
+        writeln!(
+            stdout,
+            "{}\t{}\t{}\t{} files\t{} days old",
+            version.host,
+            version.version,
+            format_size(version.stats.size),
+            version.stats.files,
+            age_in_days(version.stats.modified)
+        )
+        .context("Failed to write to stdout")?;
+    }
+
+    stdout.flush().context("Failed to flush stdout")
+}
+
+/// Print how much space the cached versions and everything else in the cache take up
+pub fn cache_size(cache_dir: &Path) -> Result<()> {
+    let versions = cached_versions(cache_dir)?;
+    let versions_size = versions.iter().map(|v| v.stats.size).sum::<u64>();
+    println!(
+        "versions\t{}\t{} versions",
+        format_size(versions_size),
+        versions.len()
+    );
+
+    let mut total = versions_size;
+    for folder in SUPPORT_FOLDERS {
+        let path = cache_dir.join(folder);
+        if !path.exists() {
+            continue;
+        }
+        let stats = folder_stats(&path)?;
+        println!("{}\t{}", folder, format_size(stats.size));
+        total += stats.size;
+    }
+
+    println!("total\t{}", format_size(total));
+    Ok(())
+}
+
+/// Remove cached versions according to `policy`, then any parsed indexes the remaining versions
+/// don't use. With `dry_run`, only print what would go.
+pub fn prune_cache(cache_dir: &Path, policy: &PrunePolicy, dry_run: bool) -> Result<()> {
+    if policy.max_age.is_none() && policy.keep.is_none() && policy.max_size.is_none() {
+        bail!("Nothing to prune by, give at least one of --older-than, --keep or --max-size");
+    }
+
+    let versions = cached_versions(cache_dir)?;
+    let pruned = select_for_pruning(&versions, policy, SystemTime::now());
+
+    let mut freed = 0;
+    for version in &pruned {
+        if !dry_run {
+            remove_version(cache_dir, version)?;
+        }
+        println!(
+            "{}\t{}\t{}",
+            version.host,
+            version.version,
+            format_size(version.stats.size)
+        );
+        freed += version.stats.size;
+    }
+
+    let remaining = versions
+        .iter()
+        .filter(|version| !pruned.iter().any(|p| p.path == version.path))
+        .collect::<Vec<_>>();
+    let unused_indexes = unused_index_entries(cache_dir, &remaining)?;
+    for (path, size) in &unused_indexes {
+        if !dry_run {
+            fs::remove_file(path).with_context(|| format!("Failed to remove {:?}", path))?;
+        }
+        freed += size;
+    }
+
+    eprintln!(
+        "{} {} of {} versions and {} unused parsed indexes, freeing {}",
+        if dry_run { "Would prune" } else { "Pruned" },
+        pruned.len(),
+        versions.len(),
+        unused_indexes.len(),
+        format_size(freed)
+    );
+    Ok(())
+}
+
+/// Remove every cached version along with the cached CDN URLs, indexes and schema
+pub fn clear_cache(cache_dir: &Path) -> Result<()> {
+    let versions = cached_versions(cache_dir)?;
+    for version in &versions {
+        remove_version(cache_dir, version)?;
+    }
+
+    for folder in SUPPORT_FOLDERS {
+        let path = cache_dir.join(folder);
+        if path.exists() {
+            fs::remove_dir_all(&path).with_context(|| format!("Failed to remove {:?}", path))?;
+        }
+    }
+
+    eprintln!("Cleared {} versions from {:?}", versions.len(), cache_dir);
+    Ok(())
+}
@@ -2,10 +2,10 @@ use std::io::{self, BufWriter, Write};
 
 use anyhow::{Context, Result};
 
-use crate::bundle_fs::FS;
+use crate::file_system::FileSystem;
 
 /// Write the contents of the file to stdout
-pub fn cat_file(fs: &mut FS, path: &str) -> Result<()> {
+pub fn cat_file(fs: &mut dyn FileSystem, path: &str) -> Result<()> {
     let contents = fs.read(path).context("Failed to read file")?;
 
     let mut stdout = BufWriter::new(io::stdout().lock());
@@ -3,10 +3,10 @@ use std::{fs, path::Path};
 use anyhow::{ensure, Context, Result};
 use glob::Pattern;
 
-use crate::bundle_fs::FS;
+use crate::file_system::FileSystem;
 
 /// Extract files to disk matching a glob pattern
-pub fn extract_art(fs: &mut FS, pattern: &Pattern, output_folder: &Path) -> Result<()> {
+pub fn extract_art(fs: &mut dyn FileSystem, pattern: &Pattern, output_folder: &Path) -> Result<()> {
     ensure!(
         pattern.as_str().ends_with(".dds"),
         "Only .dds art export is supported."
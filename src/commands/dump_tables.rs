@@ -3,7 +3,7 @@ use std::{
     path::{Path, PathBuf},
 };
 
-use anyhow::{anyhow, bail, ensure, Context, Result};
+use anyhow::{bail, ensure, Context, Result};
 use bytes::Bytes;
 use glob::Pattern;
 use polars::{
@@ -14,12 +14,12 @@ use polars::{
 };
 
 use crate::{
-    bundle_fs::FS,
     commands::Patch,
     dat::{
         ivy_schema::{fetch_schema, ColumnSchema, DatTableSchema},
         table_view::DatTable,
     },
+    file_system::FileSystem,
 };
 
 fn parse_foreignrow(bytes: &[u8]) -> u64 {
@@ -306,8 +306,7 @@ fn save_to_csv(table: &mut DataFrame, path: &Path) -> Result<()> {
 
 fn process_file(bytes: &Bytes, output_path: &Path, schema: &DatTableSchema) -> Result<()> {
     // Load dat file
-    let (_, table) = DatTable::from_raw_bytes(bytes)
-        .map_err(|e| anyhow!("Failed to parse table data: {:?}", e))?;
+    let table = DatTable::from_raw_bytes(bytes).context("Failed to parse table data")?;
 
     ensure!(!table.rows.is_empty(), "Empty table");
 
@@ -322,7 +321,7 @@ fn process_file(bytes: &Bytes, output_path: &Path, schema: &DatTableSchema) -> R
 
 /// Convert datc64 tables into CSV files
 pub fn dump_tables(
-    fs: &mut FS,
+    fs: &mut dyn FileSystem,
     pattern: &Pattern,
     cache_dir: &Path,
     output_folder: &Path,
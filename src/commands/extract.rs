@@ -6,10 +6,14 @@ use std::{
 use anyhow::{Context, Result};
 use glob::Pattern;
 
-use crate::bundle_fs::FS;
+use crate::file_system::FileSystem;
 
 /// Extract files to disk matching a glob pattern
-pub fn extract_files(fs: &mut FS, pattern: &Pattern, output_folder: &Path) -> Result<()> {
+pub fn extract_files(
+    fs: &mut dyn FileSystem,
+    pattern: &Pattern,
+    output_folder: &Path,
+) -> Result<()> {
     let filenames = fs
         .list()
         .filter(|filename| pattern.matches(filename))
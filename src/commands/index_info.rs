@@ -0,0 +1,208 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    io::{self, BufWriter, Write},
+};
+
+use anyhow::{Context, Result};
+use serde::Serialize;
+
+use crate::bundle_fs::FS;
+
+/// Summary of a single bundle
+#[derive(Debug, Serialize)]
+pub struct BundleStats {
+    pub name: String,
+    pub files: usize,
+    pub uncompressed_size: u64,
+    /// Header details, only present when bundles were loaded
+    pub compressed_size: Option<u64>,
+    pub encoding: Option<String>,
+    pub granularity: Option<u32>,
+    pub error: Option<String>,
+}
+
+/// Total size of the files directly inside a directory
+#[derive(Debug, Serialize)]
+pub struct DirectoryStats {
+    pub path: String,
+    pub files: usize,
+    pub size: u64,
+}
+
+/// Summary of an index and its bundles
+#[derive(Debug, Serialize)]
+pub struct IndexInfo {
+    pub bundle_count: usize,
+    pub file_count: usize,
+    pub path_rep_count: usize,
+    pub hash_algorithm: String,
+    pub uncompressed_size: u64,
+    pub compressed_size: Option<u64>,
+    /// Codec -> number of bundles
+    pub encodings: BTreeMap<String, usize>,
+    /// Block granularity -> number of bundles
+    pub granularities: BTreeMap<u32, usize>,
+    pub largest_directories: Vec<DirectoryStats>,
+    pub bundles: Vec<BundleStats>,
+}
+
+/// Gather statistics about the index. With `load_bundles`, every bundle header is read as well,
+/// which downloads every bundle when reading from the CDN.
+pub fn collect_index_info(fs: &FS, load_bundles: bool, top_directories: usize) -> IndexInfo {
+    let index = fs.index();
+
+    let mut bundles = index
+        .bundles
+        .iter()
+        .map(|b| BundleStats {
+            name: b.name.clone(),
+            files: 0,
+            uncompressed_size: b.uncompressed_size as u64,
+            compressed_size: None,
+            encoding: None,
+            granularity: None,
+            error: None,
+        })
+        .collect::<Vec<_>>();
+    for file in &index.files {
+        if let Some(bundle) = bundles.get_mut(file.bundle_index as usize) {
+            bundle.files += 1;
+        }
+    }
+
+    if load_bundles {
+        for (i, stats) in bundles.iter_mut().enumerate() {
+            match fs.load_bundle(i as u32) {
+                Ok(bundle) => {
+                    stats.compressed_size =
+                        Some(bundle.blocks.iter().map(|b| b.len() as u64).sum());
+                    stats.encoding = Some(format!("{:?}", bundle.head.first_file_encode));
+                    stats.granularity = Some(bundle.head.uncompressed_block_granularity);
+                }
+                Err(e) => stats.error = Some(format!("{:#}", e)),
+            }
+        }
+    }
+
+    let mut encodings = BTreeMap::<String, usize>::new();
+    let mut granularities = BTreeMap::<u32, usize>::new();
+    for bundle in &bundles {
+        if let Some(encoding) = &bundle.encoding {
+            *encodings.entry(encoding.clone()).or_default() += 1;
+        }
+        if let Some(granularity) = bundle.granularity {
+            *granularities.entry(granularity).or_default() += 1;
+        }
+    }
+
+    // Sizes of the files directly within each directory
+    let mut directories = HashMap::<&str, (usize, u64)>::new();
+    for path in fs.paths() {
+        let Ok(metadata) = fs.metadata(path) else {
+            continue;
+        };
+        let directory = path.rsplit_once('/').map_or("", |(d, _)| d);
+        let entry = directories.entry(directory).or_default();
+        entry.0 += 1;
+        entry.1 += metadata.size as u64;
+    }
+    let mut largest_directories = directories
+        .into_iter()
+        .map(|(path, (files, size))| DirectoryStats {
+            path: path.to_string(),
+            files,
+            size,
+        })
+        .collect::<Vec<_>>();
+    largest_directories.sort_by(|a, b| b.size.cmp(&a.size).then_with(|| a.path.cmp(&b.path)));
+    largest_directories.truncate(top_directories);
+
+    IndexInfo {
+        bundle_count: index.bundles.len(),
+        file_count: index.files.len(),
+        path_rep_count: index.paths.len(),
+        hash_algorithm: format!("{:?}", fs.hash_algorithm()),
+        uncompressed_size: bundles.iter().map(|b| b.uncompressed_size).sum(),
+        compressed_size: load_bundles.then(|| {
+            bundles
+                .iter()
+                .filter_map(|b| b.compressed_size)
+                .sum::<u64>()
+        }),
+        encodings,
+        granularities,
+        largest_directories,
+        bundles,
+    }
+}
+
+/// Print statistics about the index, as text or JSON
+pub fn index_info(fs: &FS, json: bool, load_bundles: bool, top_directories: usize) -> Result<()> {
+    let info = collect_index_info(fs, load_bundles, top_directories);
+
+    let mut stdout = BufWriter::new(io::stdout().lock());
+    if json {
+        serde_json::to_writer_pretty(&mut stdout, &info).context("Failed to write JSON")?;
+        writeln!(stdout).context("Failed to write to stdout")?;
+    } else {
+        write_text(&mut stdout, &info).context("Failed to write to stdout")?;
+    }
+
+    stdout.flush().context("Failed to flush stdout")
+}
+
+fn write_text(out: &mut impl Write, info: &IndexInfo) -> io::Result<()> {
+    writeln!(out, "Bundles:           {}", info.bundle_count)?;
+    writeln!(out, "Files:             {}", info.file_count)?;
+    writeln!(out, "Path reps:         {}", info.path_rep_count)?;
+    writeln!(out, "Path hash:         {}", info.hash_algorithm)?;
+    writeln!(out, "Uncompressed size: {}", info.uncompressed_size)?;
+    if let Some(compressed_size) = info.compressed_size {
+        writeln!(out, "Compressed size:   {}", compressed_size)?;
+    }
+
+    if !info.encodings.is_empty() {
+        writeln!(out, "\nEncodings:")?;
+        for (encoding, count) in &info.encodings {
+            writeln!(out, "  {}\t{}", encoding, count)?;
+        }
+    }
+
+    if !info.granularities.is_empty() {
+        writeln!(out, "\nBlock granularities:")?;
+        for (granularity, count) in &info.granularities {
+            writeln!(out, "  {}\t{}", granularity, count)?;
+        }
+    }
+
+    writeln!(out, "\nLargest directories (size, files, path):")?;
+    for directory in &info.largest_directories {
+        writeln!(
+            out,
+            "  {}\t{}\t{}",
+            directory.size, directory.files, directory.path
+        )?;
+    }
+
+    writeln!(
+        out,
+        "\nBundles (files, uncompressed, compressed, encoding, name):"
+    )?;
+    for bundle in &info.bundles {
+        let optional = |value: Option<String>| value.unwrap_or_else(|| "-".to_string());
+        writeln!(
+            out,
+            "  {}\t{}\t{}\t{}\t{}",
+            bundle.files,
+            bundle.uncompressed_size,
+            optional(bundle.compressed_size.map(|s| s.to_string())),
+            optional(bundle.encoding.clone()),
+            bundle.name
+        )?;
+        if let Some(error) = &bundle.error {
+            writeln!(out, "    error: {}", error)?;
+        }
+    }
+
+    Ok(())
+}
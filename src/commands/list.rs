@@ -3,17 +3,28 @@ use std::io::{self, BufWriter, Write};
 use anyhow::{Context, Result};
 use glob::Pattern;
 
-use crate::bundle_fs::FS;
+use crate::file_system::FileSystem;
 
-/// List filenames matching a glob pattern
-pub fn list_files(file_system: &FS, pattern: &Pattern) -> Result<()> {
+/// List filenames matching a glob pattern, optionally with the layer each one comes from
+pub fn list_files(
+    file_system: &dyn FileSystem,
+    pattern: &Pattern,
+    show_origin: bool,
+) -> Result<()> {
     // Use a buffered writer since we're dumping a lot of data
     let mut stdout = BufWriter::new(io::stdout().lock());
 
     file_system
         .list()
         .filter(|p| pattern.matches(p))
-        .try_for_each(|p| writeln!(stdout, "{}", p).context("Failed to write to stdout"))?;
+        .try_for_each(|p| {
+            let origin = show_origin.then(|| file_system.origin(&p)).flatten();
+            let written = match origin {
+                Some(origin) => writeln!(stdout, "{}\t{}", p, origin),
+                None => writeln!(stdout, "{}", p),
+            };
+            written.context("Failed to write to stdout")
+        })?;
 
     stdout.flush().context("Failed to flush stdout")
 }
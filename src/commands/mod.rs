@@ -1,8 +1,15 @@
+pub mod cache;
 pub mod cat;
 pub mod dump_art;
 pub mod dump_tables;
 pub mod extract;
+pub mod index_info;
 pub mod list;
+pub mod orphans;
+pub mod prefetch;
+pub mod replace;
+pub mod verify;
+pub mod version;
 
 #[derive(Debug, Clone)]
 pub enum Patch {
@@ -0,0 +1,38 @@
+use std::io::{self, BufWriter, Write};
+
+use anyhow::{Context, Result};
+
+use crate::bundle_fs::FS;
+
+/// Print file entries that no listed path hashes to, and warn about path reps whose hash doesn't
+/// match their directory
+pub fn find_orphans(fs: &FS) -> Result<()> {
+    let mut stdout = BufWriter::new(io::stdout().lock());
+
+    let orphans = fs.orphaned_files();
+    for orphan in &orphans {
+        writeln!(
+            stdout,
+            "{:016x}\t{}\t{}\t{}",
+            orphan.hash, orphan.bundle, orphan.offset, orphan.size
+        )
+        .context("Failed to write to stdout")?;
+    }
+    stdout.flush().context("Failed to flush stdout")?;
+
+    let mismatches = fs.check_path_reps();
+    for mismatch in &mismatches {
+        eprintln!(
+            "Path rep {} has hash {:016x}, but directory {:?} hashes to {:016x}",
+            mismatch.index, mismatch.hash, mismatch.directory, mismatch.expected_hash
+        );
+    }
+
+    eprintln!(
+        "Found {} orphaned files and {} mismatched path reps",
+        orphans.len(),
+        mismatches.len()
+    );
+
+    Ok(())
+}
@@ -0,0 +1,84 @@
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+
+use anyhow::{bail, Context, Result};
+use glob::Pattern;
+use rayon::{
+    iter::{IntoParallelRefIterator, ParallelIterator},
+    ThreadPoolBuilder,
+};
+
+use crate::bundle_fs::FS;
+
+/// Download every bundle holding a file that matches a glob pattern, `jobs` at a time, so later
+/// commands can run from the cache
+pub fn prefetch_bundles(fs: &FS, pattern: &Pattern, jobs: usize) -> Result<()> {
+    let filenames = fs
+        .list()
+        .filter(|filename| pattern.matches(filename))
+        .collect::<Vec<_>>();
+    let filenames = filenames.iter().map(|f| f.as_str()).collect::<Vec<_>>();
+    let bundles = fs.bundles_for(&filenames);
+    eprintln!(
+        "Prefetching {} bundles for {} files",
+        bundles.len(),
+        filenames.len()
+    );
+
+    let pool = ThreadPoolBuilder::new()
+        .num_threads(jobs.max(1))
+        .build()
+        .context("Failed to create download threads")?;
+
+    let done = AtomicUsize::new(0);
+    let fetched_bytes = AtomicU64::new(0);
+    let failed = pool.install(|| {
+        bundles
+            .par_iter()
+            .filter(|&&bundle_index| {
+                let name = fs
+                    .index()
+                    .bundles
+                    .get(bundle_index as usize)
+                    .map_or("<missing bundle>", |bundle| bundle.name.as_str());
+                let result = fs.fetch_bundle(bundle_index);
+                let done = done.fetch_add(1, Ordering::Relaxed) + 1;
+                match result {
+                    Ok(size) => {
+                        let total =
+                            fetched_bytes.fetch_add(size as u64, Ordering::Relaxed) + size as u64;
+                        eprintln!(
+                            "[{}/{}] {} ({} bytes, {} MiB total)",
+                            done,
+                            bundles.len(),
+                            name,
+                            size,
+                            total / (1024 * 1024)
+                        );
+                        false
+                    }
+                    Err(e) => {
+                        eprintln!(
+                            "[{}/{}] Failed to fetch {}: {:#}",
+                            done,
+                            bundles.len(),
+                            name,
+                            e
+                        );
+                        true
+                    }
+                }
+            })
+            .count()
+    });
+
+    eprintln!(
+        "Prefetched {} bundles, {} failed",
+        bundles.len() - failed,
+        failed
+    );
+    if failed > 0 {
+        bail!("Failed to fetch {} bundles", failed);
+    }
+
+    Ok(())
+}
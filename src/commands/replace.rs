@@ -0,0 +1,253 @@
+use std::{
+    collections::HashMap,
+    fs,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{bail, ensure, Context, Result};
+use bytes::Bytes;
+use murmurhash64::murmur_hash64a;
+
+use crate::{
+    bundle::{serialize_bundle, StoredEncoder, DEFAULT_BLOCK_GRANULARITY},
+    bundle_index::{detect_hash_algorithm, index_from_bytes, serialize_bundle_index, BundleInfo},
+    fs_util::write_atomic,
+    overlay_fs::collect_local_files,
+};
+
+/// Name of the bundle that replacement files are written to, relative to `Bundles2/`
+const REPLACEMENT_BUNDLE: &str = "poe_tools/replacements";
+
+fn index_paths(steam_folder: &Path) -> (PathBuf, PathBuf) {
+    let index_path = steam_folder.join("Bundles2/_.index.bin");
+    let backup_path = index_path.with_extension("bin.bak");
+    (index_path, backup_path)
+}
+
+/// Hashes of the original index that was backed up, and of the index written in its place. The
+/// game rewriting the index in a patch shows up as the live index matching neither.
+struct ReplaceRecord {
+    original: u64,
+    replaced: u64,
+}
+
+impl ReplaceRecord {
+    fn path(steam_folder: &Path) -> PathBuf {
+        steam_folder.join("Bundles2/_.index.bin.replaced")
+    }
+
+    fn load(steam_folder: &Path) -> Result<Option<Self>> {
+        let path = Self::path(steam_folder);
+        if !path.exists() {
+            return Ok(None);
+        }
+
+        let contents = fs::read_to_string(&path).context("Failed to read replace record")?;
+        let mut hashes = contents
+            .lines()
+            .map(|line| u64::from_str_radix(line.trim(), 16));
+        match (hashes.next(), hashes.next()) {
+            (Some(Ok(original)), Some(Ok(replaced))) => Ok(Some(Self { original, replaced })),
+            _ => bail!("Malformed replace record: {:?}", path),
+        }
+    }
+
+    fn store(&self, steam_folder: &Path) -> Result<()> {
+        let contents = format!("{:016x}\n{:016x}\n", self.original, self.replaced);
+        write_atomic(&Self::path(steam_folder), contents.as_bytes())
+            .context("Failed to write replace record")
+    }
+
+    /// Whether the live index is either the original or the one written over it
+    fn matches(&self, live_index: &[u8]) -> bool {
+        let hash = hash_index(live_index);
+        hash == self.original || hash == self.replaced
+    }
+}
+
+fn hash_index(bytes: &[u8]) -> u64 {
+    murmur_hash64a(bytes, 0x1337b33f)
+}
+
+fn replacement_bundle_path(steam_folder: &Path) -> PathBuf {
+    steam_folder.join(format!("Bundles2/{}.bundle.bin", REPLACEMENT_BUNDLE))
+}
+
+/// Override virtual files in a steam install with the contents of `replacement_folder`, which
+/// mirrors the virtual folder layout. The original index is kept as a backup, and every run
+/// starts from it so replacements don't stack up. If the game has rewritten the index since, the
+/// backup is refreshed from it instead.
+pub fn replace_files(steam_folder: &Path, replacement_folder: &Path) -> Result<()> {
+    let (index_path, backup_path) = index_paths(steam_folder);
+    let live_index = fs::read(&index_path).context("Failed to read index")?;
+
+    let original_index = if !backup_path.exists() {
+        write_atomic(&backup_path, &live_index).context("Failed to back up index")?;
+        eprintln!("Backed up index to {:?}", backup_path);
+        live_index
+    } else {
+        let Some(record) = ReplaceRecord::load(steam_folder)? else {
+            bail!(
+                "Found an index backup without a record of what replaced it. Delete {:?} if the \
+                 game has been updated since, or restore it first.",
+                backup_path
+            );
+        };
+        if record.matches(&live_index) {
+            fs::read(&backup_path).context("Failed to read index backup")?
+        } else {
+            write_atomic(&backup_path, &live_index).context("Failed to back up index")?;
+            eprintln!(
+                "Index has changed since files were last replaced, refreshed backup at {:?}",
+                backup_path
+            );
+            live_index
+        }
+    };
+    let original_hash = hash_index(&original_index);
+    let mut index =
+        index_from_bytes(Bytes::from(original_index)).context("Failed to load original index")?;
+
+    let files =
+        collect_local_files(replacement_folder).context("Failed to collect replacement files")?;
+
+    let hash_algorithm = detect_hash_algorithm(&index);
+    let lut = index
+        .files
+        .iter()
+        .enumerate()
+        .map(|(i, f)| (f.hash, i))
+        .collect::<HashMap<_, _>>();
+
+    // Pack the replacements into a single bundle, and repoint their index entries at it
+    let bundle_index = index.bundles.len() as u32;
+    let mut content = vec![];
+    for (virtual_path, path) in &files {
+        let Some(&file_index) = lut.get(&hash_algorithm.hash_file(virtual_path)) else {
+            bail!("Path not found in index: {}", virtual_path);
+        };
+
+        let bytes = fs::read(path).with_context(|| format!("Failed to read file: {:?}", path))?;
+        let file = &mut index.files[file_index];
+        file.bundle_index = bundle_index;
+        file.offset = u32::try_from(content.len()).context("Replacement bundle too large")?;
+        file.size = u32::try_from(bytes.len()).context("Replacement file too large")?;
+        content.extend(bytes);
+
+        eprintln!("Replacing file: {}", virtual_path);
+    }
+    index.bundles.push(BundleInfo {
+        name: REPLACEMENT_BUNDLE.to_string(),
+        uncompressed_size: u32::try_from(content.len()).context("Replacement bundle too large")?,
+    });
+
+    // Write the bundle first so the new index never points at a missing file
+    let bundle_path = replacement_bundle_path(steam_folder);
+    fs::create_dir_all(bundle_path.parent().context("Failed to get path parent")?)
+        .context("Failed to create bundle folder")?;
+    let bundle = serialize_bundle(&content, DEFAULT_BLOCK_GRANULARITY, &StoredEncoder)
+        .context("Failed to serialize replacement bundle")?;
+    write_atomic(&bundle_path, &bundle).context("Failed to write replacement bundle")?;
+
+    // Record the new index before writing it, so it's recognised whichever way a crash falls
+    let index_bytes =
+        serialize_bundle_index(&index, &StoredEncoder).context("Failed to serialize index")?;
+    ReplaceRecord {
+        original: original_hash,
+        replaced: hash_index(&index_bytes),
+    }
+    .store(steam_folder)?;
+    write_atomic(&index_path, &index_bytes).context("Failed to replace index")?;
+
+    eprintln!("Replaced {} files", files.len());
+    Ok(())
+}
+
+/// Undo `replace_files`, restoring the original index and removing the replacement bundle.
+/// Refuses if the game has rewritten the index since, as the backup would then be from an older
+/// patch.
+pub fn restore_files(steam_folder: &Path) -> Result<()> {
+    let (index_path, backup_path) = index_paths(steam_folder);
+    if !backup_path.exists() {
+        bail!("No index backup found at {:?}", backup_path);
+    }
+
+    let record = ReplaceRecord::load(steam_folder)?
+        .context("No record of the replaced index, can't tell if the backup is still current")?;
+    let live_index = fs::read(&index_path).context("Failed to read index")?;
+    ensure!(
+        record.matches(&live_index),
+        "Index has changed since files were replaced, probably in a game update. Not restoring \
+         the backup from the older patch, delete {:?} instead.",
+        backup_path
+    );
+
+    fs::rename(&backup_path, &index_path).context("Failed to restore index")?;
+    fs::remove_file(ReplaceRecord::path(steam_folder))
+        .context("Failed to remove replace record")?;
+
+    let bundle_path = replacement_bundle_path(steam_folder);
+    if bundle_path.exists() {
+        fs::remove_file(&bundle_path).context("Failed to remove replacement bundle")?;
+    }
+
+    eprintln!("Restored original index");
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::{replace_files, replacement_bundle_path, restore_files};
+    use crate::{bundle_fs::FS, test_support::TestBundles};
+
+    #[test]
+    fn replace_and_restore() {
+        let steam_folder =
+            std::env::temp_dir().join(format!("poe_tools_replace_{}", std::process::id()));
+        let index_path = steam_folder.join("Bundles2/_.index.bin");
+        TestBundles::new()
+            .bundle(
+                "data",
+                b"hello world",
+                &[("data/a.txt", 0, 5), ("data/b.txt", 6, 5)],
+            )
+            .write_to(&steam_folder.join("Bundles2"));
+        let replacements = steam_folder.join("replacements");
+        fs::create_dir_all(replacements.join("data")).unwrap();
+        fs::write(replacements.join("data/a.txt"), "goodbye").unwrap();
+
+        // Replacing twice starts from the original both times
+        replace_files(&steam_folder, &replacements).unwrap();
+        replace_files(&steam_folder, &replacements).unwrap();
+        let file_system = FS::from_steam(steam_folder.clone()).unwrap();
+        assert_eq!(file_system.read("data/a.txt").unwrap(), "goodbye");
+        assert_eq!(file_system.read("data/b.txt").unwrap(), "world");
+        drop(file_system);
+
+        restore_files(&steam_folder).unwrap();
+        let file_system = FS::from_steam(steam_folder.clone()).unwrap();
+        assert_eq!(file_system.read("data/a.txt").unwrap(), "hello");
+        assert!(!replacement_bundle_path(&steam_folder).exists());
+        drop(file_system);
+
+        // A game update after replacing must not be rolled back to the old patch
+        replace_files(&steam_folder, &replacements).unwrap();
+        let patched = TestBundles::new()
+            .bundle("data", b"patched!", &[("data/a.txt", 0, 8)])
+            .index_bytes();
+        fs::write(&index_path, &patched).unwrap();
+        assert!(restore_files(&steam_folder).is_err());
+        assert_eq!(fs::read(&index_path).unwrap(), patched);
+
+        // Replacing again backs up the new index instead
+        replace_files(&steam_folder, &replacements).unwrap();
+        assert_eq!(
+            fs::read(index_path.with_extension("bin.bak")).unwrap(),
+            patched
+        );
+
+        fs::remove_dir_all(steam_folder).unwrap();
+    }
+}
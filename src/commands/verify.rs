@@ -0,0 +1,70 @@
+use anyhow::{bail, Result};
+use rayon::iter::{IntoParallelIterator, ParallelIterator};
+
+use crate::bundle_fs::FS;
+
+/// Check every bundle exists, parses and decompresses to the size the index expects, and that
+/// every file lies within its bundle. Prints a report per bundle, failing if any bundle does.
+pub fn verify_bundles(fs: &FS) -> Result<()> {
+    let index = fs.index();
+
+    // Problems with the index itself, grouped by bundle
+    let mut problems = vec![vec![]; index.bundles.len()];
+    let mut orphaned_files = 0;
+    for file in &index.files {
+        let Some(bundle) = index.bundles.get(file.bundle_index as usize) else {
+            eprintln!(
+                "File {:016x} refers to missing bundle {}",
+                file.hash, file.bundle_index
+            );
+            orphaned_files += 1;
+            continue;
+        };
+
+        if file.offset as u64 + file.size as u64 > bundle.uncompressed_size as u64 {
+            problems[file.bundle_index as usize].push(format!(
+                "File {:016x} at {}..{} lies outside of the bundle",
+                file.hash,
+                file.offset,
+                file.offset as u64 + file.size as u64
+            ));
+        }
+    }
+
+    let results = (0..index.bundles.len())
+        .into_par_iter()
+        .map(|i| fs.verify_bundle(i as u32))
+        .collect::<Vec<_>>();
+
+    let mut failed = 0;
+    for ((bundle, result), mut problems) in index.bundles.iter().zip(results).zip(problems) {
+        if let Err(e) = result {
+            problems.insert(0, format!("{:#}", e));
+        }
+
+        if problems.is_empty() {
+            println!("OK\t{}", bundle.name);
+        } else {
+            failed += 1;
+            println!("FAIL\t{}", bundle.name);
+            for problem in problems {
+                println!("\t{}", problem);
+            }
+        }
+    }
+
+    eprintln!(
+        "Verified {} bundles, {} failed",
+        index.bundles.len(),
+        failed
+    );
+    if failed > 0 || orphaned_files > 0 {
+        bail!(
+            "Verification failed: {} bad bundles, {} files in missing bundles",
+            failed,
+            orphaned_files
+        );
+    }
+
+    Ok(())
+}
@@ -0,0 +1,21 @@
+use std::io::{self, BufWriter, Write};
+
+use anyhow::{Context, Result};
+
+use crate::bundle_loader::{fetch_patch_info, POE1_PATCH_SERVER, POE2_PATCH_SERVER};
+
+/// Print the live version of each game as reported by its patch server, along with its CDN URLs
+pub fn print_versions() -> Result<()> {
+    let mut stdout = BufWriter::new(io::stdout().lock());
+
+    for (game, server) in [("1", POE1_PATCH_SERVER), ("2", POE2_PATCH_SERVER)] {
+        let info = fetch_patch_info(&server)
+            .with_context(|| format!("Failed to query patch server: {}", server.host))?;
+        writeln!(stdout, "{}\t{}", game, info.version).context("Failed to write to stdout")?;
+        for url in &info.urls {
+            writeln!(stdout, "\t{}", url).context("Failed to write to stdout")?;
+        }
+    }
+
+    stdout.flush().context("Failed to flush stdout")
+}
@@ -1,6 +1,6 @@
 use std::fmt::Display;
 
-use anyhow::{ensure, Context, Result};
+use anyhow::{ensure, Result};
 use nom::{
     bytes::complete::{tag, take_until},
     combinator::map_res,
@@ -9,6 +9,8 @@ use nom::{
     IResult,
 };
 
+use crate::parse_error::ParseError;
+
 /// Splits a byte slice into two parts around 16 consecutive 0xBB bytes.
 /// Returns a tuple containing the two halves.
 fn split_on_8_bb(input: &[u8]) -> IResult<&[u8], (&[u8], &[u8])> {
@@ -33,14 +35,14 @@ fn split_on_8_bb(input: &[u8]) -> IResult<&[u8], (&[u8], &[u8])> {
 }
 
 // Take a null-terminated UTF-16 string
-fn take_utf16_string(input: &[u8]) -> String {
+fn take_utf16_string(input: &[u8]) -> Result<String, std::string::FromUtf16Error> {
     let u16_data = input
         .chunks_exact(2)
-        .map(|c| u16::from_le_bytes(c.try_into().unwrap()))
+        .map(|c| u16::from_le_bytes([c[0], c[1]]))
         .take_while(|x| *x != 0)
         .collect::<Vec<_>>();
 
-    String::from_utf16(&u16_data).expect("Failed to parse UTF-16 string.")
+    String::from_utf16(&u16_data)
 }
 pub struct DatTable {
     pub rows: Vec<Vec<u8>>,
@@ -49,15 +51,28 @@ pub struct DatTable {
 
 impl DatTable {
     /// Parses a raw datc64 file
-    pub fn from_raw_bytes(bytes: &[u8]) -> IResult<&[u8], Self> {
-        let (input, num_rows) = le_u32(bytes)?;
+    pub fn from_raw_bytes(bytes: &[u8]) -> Result<Self, ParseError> {
+        let (input, num_rows) =
+            le_u32(bytes).map_err(|e| ParseError::from_nom("dat table", bytes, e))?;
 
-        let (input, (fixed_data, variable_data)) = split_on_8_bb(input)?;
+        let (_, (fixed_data, variable_data)) =
+            split_on_8_bb(input).map_err(|e| ParseError::from_nom("dat table", bytes, e))?;
 
         let rows = if num_rows == 0 {
             vec![]
         } else {
             let bytes_per_row = fixed_data.len() / num_rows as usize;
+            if bytes_per_row == 0 {
+                return Err(ParseError::new(
+                    "dat table",
+                    4,
+                    format!(
+                        "{} rows don't fit in {} bytes of fixed data",
+                        num_rows,
+                        fixed_data.len()
+                    ),
+                ));
+            }
 
             fixed_data
                 .chunks_exact(bytes_per_row)
@@ -65,13 +80,10 @@ impl DatTable {
                 .collect::<Vec<_>>()
         };
 
-        Ok((
-            input,
-            Self {
-                rows,
-                variable_data: variable_data.to_vec(),
-            },
-        ))
+        Ok(Self {
+            rows,
+            variable_data: variable_data.to_vec(),
+        })
     }
 
     /// Number of bytes in a row
@@ -82,7 +94,9 @@ impl DatTable {
     /// Returns column values as slices
     pub fn view_col(&self, offset: usize, width: usize) -> Result<impl Iterator<Item = &[u8]>> {
         ensure!(
-            offset + width <= self.width(),
+            offset
+                .checked_add(width)
+                .is_some_and(|end| end <= self.width()),
             "Requested column out of bounds"
         );
         let iter = self
@@ -99,21 +113,32 @@ impl DatTable {
         offset: usize,
         dtype_width: usize,
     ) -> Result<impl Iterator<Item = Result<Vec<&[u8]>>>> {
+        ensure!(dtype_width > 0, "Array values must have a width");
         let iter = self.view_col(offset, 16)?.map(move |bytes| {
             let length = u64::from_le_bytes(bytes[..8].try_into().unwrap()) as usize;
             let pointer = u64::from_le_bytes(bytes[8..].try_into().unwrap()) as usize;
 
             // Check bounds
-            let start = pointer.checked_sub(8).context("underflow")?;
-            let end = start
-                .checked_add(length.checked_mul(dtype_width).context("Overflow")?)
-                .context("Overflow")?;
-            ensure!(end < self.variable_data.len(), "Array slice oveflow");
-
-            let bytes = self.variable_data[start..end]
+            let range = pointer
+                .checked_sub(8)
+                .zip(length.checked_mul(dtype_width))
+                .and_then(|(start, size)| Some(start..start.checked_add(size)?))
+                .filter(|range| range.end < self.variable_data.len())
+                .ok_or_else(|| {
+                    ParseError::new(
+                        "dat array",
+                        pointer,
+                        format!(
+                            "{} values of {} bytes lie outside of the variable data",
+                            length, dtype_width
+                        ),
+                    )
+                })?;
+
+            let bytes = self.variable_data[range]
                 .chunks_exact(dtype_width)
                 .collect();
-            Ok(bytes)
+            Ok::<_, anyhow::Error>(bytes)
         });
 
         Ok(iter)
@@ -124,22 +149,12 @@ impl DatTable {
         &self,
         offset: usize,
     ) -> Result<impl Iterator<Item = Result<Option<String>>> + '_> {
-        let iter = self.view_col(offset, 8)?.map(move |bytes| {
-            let pointer = u64::from_le_bytes(bytes.try_into().unwrap()) as usize;
-            ensure!(
-                pointer >= 8 && pointer < self.variable_data.len() + 8,
-                "Array pointer out of bounds"
-            );
-
-            let string = take_utf16_string(&self.variable_data[pointer - 8..]);
-            let string = if string.is_empty() {
-                None
-            } else {
-                Some(string)
-            };
-
-            Ok(string)
-        });
+        let iter = self
+            .view_col(offset, 8)?
+            .map(move |bytes| -> Result<Option<String>> {
+                let pointer = u64::from_le_bytes(bytes.try_into().unwrap()) as usize;
+                self.string_at(pointer).map_err(Into::into)
+            });
 
         Ok(iter)
     }
@@ -164,18 +179,37 @@ impl DatTable {
         &self,
         offset: usize,
     ) -> Result<impl Iterator<Item = Result<Vec<Option<String>>>> + '_> {
-        let iter = self.view_col_as_array_of(offset, 8, |bytes| {
-            let pointer = u64::from_le_bytes(bytes.try_into().unwrap()) as usize;
-            let string = take_utf16_string(&self.variable_data[pointer - 8..]);
-            if string.is_empty() {
-                None
-            } else {
-                Some(string)
-            }
-        })?;
+        let iter = self
+            .view_col_as_array_of(offset, 8, |bytes| {
+                let pointer = u64::from_le_bytes(bytes.try_into().unwrap()) as usize;
+                self.string_at(pointer)
+            })?
+            .map(|strings| -> Result<Vec<Option<String>>> {
+                strings?
+                    .into_iter()
+                    .collect::<Result<_, _>>()
+                    .map_err(Into::into)
+            });
 
         Ok(iter)
     }
+
+    /// Read a null-terminated UTF-16 string from the variable data section. Pointers, and the
+    /// offsets of errors, include the 8 byte marker at the start of the section.
+    fn string_at(&self, pointer: usize) -> Result<Option<String>, ParseError> {
+        let data = pointer
+            .checked_sub(8)
+            .filter(|&start| start < self.variable_data.len())
+            .map(|start| &self.variable_data[start..])
+            .ok_or_else(|| {
+                ParseError::new("dat string", pointer, "String pointer out of bounds")
+            })?;
+
+        let string = take_utf16_string(data)
+            .map_err(|e| ParseError::new("dat string", pointer, e.to_string()))?;
+
+        Ok((!string.is_empty()).then_some(string))
+    }
 }
 
 impl Display for DatTable {
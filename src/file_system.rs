@@ -0,0 +1,25 @@
+use anyhow::Result;
+use bytes::Bytes;
+
+/// Result of reading a single file as part of a batch
+pub type BatchReadItem<'a> = Result<(&'a str, Bytes), (&'a str, anyhow::Error)>;
+
+/// Common interface over the archive formats, so commands work with any of them
+pub trait FileSystem {
+    /// Lists all paths in the archive
+    fn list(&self) -> Box<dyn Iterator<Item = String> + '_>;
+
+    /// Read a single file
+    fn read(&self, path: &str) -> Result<Bytes>;
+
+    /// Read many files at once. Does not preserve order of paths given.
+    fn batch_read<'a>(
+        &'a self,
+        paths: &[&'a str],
+    ) -> Box<dyn Iterator<Item = BatchReadItem<'a>> + 'a>;
+
+    /// Describe which layer a file comes from, for file systems built from several layers
+    fn origin(&self, _path: &str) -> Option<String> {
+        None
+    }
+}
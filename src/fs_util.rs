@@ -0,0 +1,55 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{Context, Result};
+
+/// Append a suffix to a path's file name, e.g. `.part`
+pub(crate) fn with_suffix(path: &Path, suffix: &str) -> PathBuf {
+    let mut path = path.as_os_str().to_owned();
+    path.push(suffix);
+    PathBuf::from(path)
+}
+
+/// Where a file is written before it's complete, next to where it will end up
+pub(crate) fn partial_path(path: &Path) -> PathBuf {
+    with_suffix(path, ".part")
+}
+
+/// Move a completed partial file into place
+pub(crate) fn finish_partial(path: &Path) -> Result<()> {
+    let part_path = partial_path(path);
+    fs::rename(&part_path, path).with_context(|| format!("Failed to move {:?} into place", path))
+}
+
+/// Write a file to the side and rename it into place, so a crash never leaves it half written
+pub(crate) fn write_atomic(path: &Path, contents: &[u8]) -> Result<()> {
+    let part_path = partial_path(path);
+    fs::write(&part_path, contents).with_context(|| format!("Failed to write {:?}", part_path))?;
+    finish_partial(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::{partial_path, write_atomic};
+
+    #[test]
+    fn replace_file_atomically() {
+        let folder = std::env::temp_dir().join(format!("poe_tools_fs_util_{}", std::process::id()));
+        fs::create_dir_all(&folder).unwrap();
+
+        // Files sharing a stem get their own partial files
+        let path = folder.join("index.bin");
+        assert_ne!(partial_path(&path), partial_path(&folder.join("index.bak")));
+
+        fs::write(&path, "old").unwrap();
+        write_atomic(&path, b"new").unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "new");
+        assert!(!partial_path(&path).exists());
+
+        fs::remove_dir_all(folder).unwrap();
+    }
+}
@@ -0,0 +1,339 @@
+use std::{
+    collections::{HashMap, HashSet},
+    path::Path,
+};
+
+use anyhow::{bail, ensure, Context, Result};
+use bytes::Bytes;
+use nom::{
+    bytes::complete::take,
+    multi::count,
+    number::complete::{le_u16, le_u32, le_u64},
+    IResult,
+};
+
+use crate::{
+    bundle::map_file,
+    file_system::{BatchReadItem, FileSystem},
+    parse_error::ParseError,
+};
+
+/// Every record starts with a u32 length and a 4 byte tag
+const RECORD_HEADER_SIZE: usize = 8;
+
+/// A legacy `Content.ggpk` archive, as used before bundles were introduced
+pub struct GGPK {
+    data: Bytes,
+    /// Lowercased path -> data range within the archive
+    files: HashMap<String, (usize, usize)>,
+    paths: Vec<String>,
+}
+
+struct DirectoryRecord {
+    name: String,
+    entries: Vec<u64>,
+}
+
+// Parser for the length and tag of a record
+fn parse_record_header(input: &[u8]) -> IResult<&[u8], (u32, &[u8])> {
+    let (input, length) = le_u32(input)?;
+    let (input, tag) = take(4usize)(input)?;
+    Ok((input, (length, tag)))
+}
+
+// Parser for the GGPK record body, returning the version and root directory offset
+fn parse_ggpk_record(input: &[u8]) -> IResult<&[u8], (u32, u64)> {
+    let (input, version) = le_u32(input)?;
+    let (input, root_offset) = le_u64(input)?;
+    let (input, _) = le_u64(input)?; // First free record
+    Ok((input, (version, root_offset)))
+}
+
+// Parser for a null-terminated name, stored as UTF-32 in version 4 archives and UTF-16 otherwise
+fn parse_name(input: &[u8], name_length: u32, wide: bool) -> IResult<&[u8], String> {
+    let invalid =
+        |input| nom::Err::Failure(nom::error::Error::new(input, nom::error::ErrorKind::Verify));
+
+    if wide {
+        let (rest, chars) = count(le_u32, name_length as usize)(input)?;
+        let name = chars
+            .into_iter()
+            .take_while(|&c| c != 0)
+            .map(char::from_u32)
+            .collect::<Option<String>>()
+            .ok_or_else(|| invalid(input))?;
+        Ok((rest, name))
+    } else {
+        let (rest, units) = count(le_u16, name_length as usize)(input)?;
+        let units = units
+            .into_iter()
+            .take_while(|&c| c != 0)
+            .collect::<Vec<_>>();
+        let name = String::from_utf16(&units).map_err(|_| invalid(input))?;
+        Ok((rest, name))
+    }
+}
+
+// Parser for a PDIR record body
+fn parse_directory(input: &[u8], wide: bool) -> IResult<&[u8], DirectoryRecord> {
+    let (input, name_length) = le_u32(input)?;
+    let (input, entry_count) = le_u32(input)?;
+    let (input, _) = take(32usize)(input)?; // SHA-256 of the contents
+    let (input, name) = parse_name(input, name_length, wide)?;
+    let (input, entries) = count(parse_directory_entry, entry_count as usize)(input)?;
+    Ok((input, DirectoryRecord { name, entries }))
+}
+
+// Parser for a directory entry, returning the offset of the child record
+fn parse_directory_entry(input: &[u8]) -> IResult<&[u8], u64> {
+    let (input, _) = le_u32(input)?; // Name hash
+    le_u64(input)
+}
+
+// Parser for a FILE record body, up to the start of the file's data
+fn parse_file(input: &[u8], wide: bool) -> IResult<&[u8], String> {
+    let (input, name_length) = le_u32(input)?;
+    let (input, _) = take(32usize)(input)?; // SHA-256 of the data
+    parse_name(input, name_length, wide)
+}
+
+/// Find the record at an offset, returning its tag and body
+fn record_at(data: &[u8], offset: u64) -> Result<(&[u8], &[u8]), ParseError> {
+    let start = usize::try_from(offset).unwrap_or(usize::MAX);
+    let record = data
+        .get(start..)
+        .ok_or_else(|| ParseError::new("GGPK record", start, "Record offset out of bounds"))?;
+    let (_, (length, tag)) = parse_record_header(record)
+        .map_err(|e| ParseError::from_nom("GGPK record header", data, e))?;
+
+    let body = record
+        .get(RECORD_HEADER_SIZE..length as usize)
+        .ok_or_else(|| ParseError::new("GGPK record", start, "Invalid record length"))?;
+    Ok((tag, body))
+}
+
+impl GGPK {
+    /// Open a GGPK file from disk
+    pub fn open(path: &Path) -> Result<GGPK> {
+        let data = map_file(path).context("Failed to read GGPK file")?;
+        Self::from_bytes(data)
+    }
+
+    /// Walk the directory tree of an archive
+    pub fn from_bytes(data: Bytes) -> Result<GGPK> {
+        let (tag, body) = record_at(&data, 0).context("Failed to read GGPK record")?;
+        ensure!(tag == b"GGPK", "Not a GGPK file");
+        let (_, (version, root_offset)) =
+            parse_ggpk_record(body).map_err(|e| ParseError::from_nom("GGPK record", &data, e))?;
+        let wide = version == 4;
+
+        let mut files = HashMap::new();
+        let mut paths = vec![];
+        let mut visited = HashSet::new();
+
+        // Directories to visit, along with the path of their parent
+        let mut stack = vec![(root_offset, String::new())];
+        while let Some((offset, parent)) = stack.pop() {
+            ensure!(
+                visited.insert(offset),
+                "Directory visited twice at offset {}",
+                offset
+            );
+
+            let (tag, body) = record_at(&data, offset)?;
+            ensure!(tag == b"PDIR", "Expected directory at offset {}", offset);
+            let (_, directory) = parse_directory(body, wide)
+                .map_err(|e| ParseError::from_nom("GGPK directory", &data, e))?;
+            let directory_path = if directory.name.is_empty() {
+                parent
+            } else {
+                format!("{}{}/", parent, directory.name)
+            };
+
+            for child in directory.entries {
+                let (tag, body) = record_at(&data, child)?;
+                match tag {
+                    b"PDIR" => stack.push((child, directory_path.clone())),
+                    b"FILE" => {
+                        let (rest, name) = parse_file(body, wide)
+                            .map_err(|e| ParseError::from_nom("GGPK file", &data, e))?;
+
+                        let start = child as usize + RECORD_HEADER_SIZE + body.len() - rest.len();
+                        let end = start + rest.len();
+                        let path = format!("{}{}", directory_path, name);
+                        files.insert(path.to_lowercase(), (start, end));
+                        paths.push(path);
+                    }
+                    b"FREE" => {}
+                    _ => bail!("Unknown record type at offset {}: {:?}", child, tag),
+                }
+            }
+        }
+
+        Ok(GGPK { data, files, paths })
+    }
+
+    /// Lists all paths in the archive
+    pub fn list(&self) -> impl Iterator<Item = String> + '_ {
+        self.paths.iter().cloned()
+    }
+
+    /// Whether a file exists in the archive
+    pub fn exists(&self, path: &str) -> bool {
+        self.files.contains_key(&path.to_lowercase())
+    }
+
+    pub fn read(&self, path: &str) -> Result<Bytes> {
+        self.files
+            .get(&path.to_lowercase())
+            .map(|&(start, end)| self.data.slice(start..end))
+            .with_context(|| format!("Path not found in GGPK: {}", path))
+    }
+
+    /// Read many files at once. Files are already uncompressed so this is just a series of reads.
+    pub fn batch_read<'a>(
+        &'a self,
+        paths: &[&'a str],
+    ) -> impl Iterator<Item = BatchReadItem<'a>> + 'a {
+        paths.to_vec().into_iter().map(move |path| {
+            self.read(path)
+                .map(|contents| (path, contents))
+                .map_err(|e| (path, e))
+        })
+    }
+}
+
+impl FileSystem for GGPK {
+    fn list(&self) -> Box<dyn Iterator<Item = String> + '_> {
+        Box::new(GGPK::list(self))
+    }
+
+    fn read(&self, path: &str) -> Result<Bytes> {
+        GGPK::read(self, path)
+    }
+
+    fn batch_read<'a>(
+        &'a self,
+        paths: &[&'a str],
+    ) -> Box<dyn Iterator<Item = BatchReadItem<'a>> + 'a> {
+        Box::new(GGPK::batch_read(self, paths))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bytes::Bytes;
+
+    use super::GGPK;
+    use crate::{bundle_fs::FS, test_support::TestBundles};
+
+    fn utf16(name: &str) -> Vec<u8> {
+        name.encode_utf16()
+            .chain([0])
+            .flat_map(u16::to_le_bytes)
+            .collect()
+    }
+
+    fn record(tag: &[u8; 4], body: &[u8]) -> Vec<u8> {
+        let mut bytes = ((body.len() + 8) as u32).to_le_bytes().to_vec();
+        bytes.extend(tag);
+        bytes.extend(body);
+        bytes
+    }
+
+    fn file_record(name: &str, data: &[u8]) -> Vec<u8> {
+        let mut body = ((name.len() + 1) as u32).to_le_bytes().to_vec();
+        body.extend([0; 32]);
+        body.extend(utf16(name));
+        body.extend(data);
+        record(b"FILE", &body)
+    }
+
+    fn dir_record(name: &str, children: &[u64]) -> Vec<u8> {
+        let mut body = ((name.len() + 1) as u32).to_le_bytes().to_vec();
+        body.extend((children.len() as u32).to_le_bytes());
+        body.extend([0; 32]);
+        body.extend(utf16(name));
+        for child in children {
+            body.extend(0u32.to_le_bytes());
+            body.extend(child.to_le_bytes());
+        }
+        record(b"PDIR", &body)
+    }
+
+    /// Builds an archive with `a.txt` and `Data/Mods.dat`
+    fn sample_ggpk() -> Vec<u8> {
+        let mut bytes = vec![0; 28];
+        let a = bytes.len() as u64;
+        bytes.extend(file_record("a.txt", b"hello"));
+        let mods = bytes.len() as u64;
+        bytes.extend(file_record("Mods.dat", b"mods"));
+        let data = bytes.len() as u64;
+        bytes.extend(dir_record("Data", &[mods]));
+        let root = bytes.len() as u64;
+        bytes.extend(dir_record("", &[a, data]));
+
+        let mut header = 3u32.to_le_bytes().to_vec();
+        header.extend(root.to_le_bytes());
+        header.extend(0u64.to_le_bytes());
+        bytes[..28].copy_from_slice(&record(b"GGPK", &header));
+        bytes
+    }
+
+    /// Builds a standalone-client archive, with `data/a.txt` and `data/b.txt` stored in a bundle
+    fn bundled_ggpk() -> Vec<u8> {
+        let bundles = TestBundles::new().bundle(
+            "data",
+            b"hello world",
+            &[("data/a.txt", 0, 5), ("data/b.txt", 6, 5)],
+        );
+
+        let mut bytes = vec![0; 28];
+        let index_offset = bytes.len() as u64;
+        bytes.extend(file_record("_.index.bin", &bundles.index_bytes()));
+        let bundle_offset = bytes.len() as u64;
+        bytes.extend(file_record(
+            "data.bundle.bin",
+            &bundles.bundle_bytes("data"),
+        ));
+        let bundles = bytes.len() as u64;
+        bytes.extend(dir_record("Bundles2", &[index_offset, bundle_offset]));
+        let root = bytes.len() as u64;
+        bytes.extend(dir_record("", &[bundles]));
+
+        let mut header = 3u32.to_le_bytes().to_vec();
+        header.extend(root.to_le_bytes());
+        header.extend(0u64.to_le_bytes());
+        bytes[..28].copy_from_slice(&record(b"GGPK", &header));
+        bytes
+    }
+
+    #[test]
+    fn read_bundles_from_archive() {
+        let ggpk = GGPK::from_bytes(Bytes::from(bundled_ggpk())).unwrap();
+        assert!(ggpk.exists("Bundles2/_.index.bin"));
+
+        let fs = FS::from_ggpk(ggpk).unwrap();
+        assert_eq!(fs.read("data/a.txt").unwrap(), "hello");
+        assert_eq!(fs.read("Data/B.txt").unwrap(), "world");
+    }
+
+    #[test]
+    fn read_sample_archive() {
+        let ggpk = GGPK::from_bytes(Bytes::from(sample_ggpk())).unwrap();
+
+        let mut paths = ggpk.list().collect::<Vec<_>>();
+        paths.sort();
+        assert_eq!(paths, ["Data/Mods.dat", "a.txt"]);
+
+        assert_eq!(ggpk.read("data/mods.dat").unwrap(), "mods");
+        assert_eq!(ggpk.read("a.txt").unwrap(), "hello");
+        assert!(ggpk.read("missing.txt").is_err());
+    }
+
+    #[test]
+    fn reject_truncated_archive() {
+        let bytes = sample_ggpk();
+        assert!(GGPK::from_bytes(Bytes::from(bytes[..bytes.len() - 10].to_vec())).is_err());
+    }
+}
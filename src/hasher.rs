@@ -34,3 +34,73 @@ impl BuildHasher for BuildMurmurHash64A {
         MurmurHash64A::new(self.seed)
     }
 }
+
+/// 64 bit FNV-1a
+pub fn fnv1a64(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf29ce484222325, |hash, &byte| {
+        (hash ^ byte as u64).wrapping_mul(0x100000001b3)
+    })
+}
+
+/// How an index hashes the paths it contains
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum PathHashAlgorithm {
+    /// MurmurHash64A over the lowercased path, used from 3.21.2 onwards
+    #[default]
+    Murmur64A,
+    /// FNV1a-64 over the path with a `++` suffix, used by older indexes. Only file paths are
+    /// lowercased.
+    Fnv1a64,
+}
+
+impl PathHashAlgorithm {
+    /// Hash a virtual file path
+    pub fn hash_file(self, path: &str) -> u64 {
+        match self {
+            Self::Murmur64A => murmur64a(&path.to_lowercase()),
+            Self::Fnv1a64 => fnv1a64(format!("{}++", path.to_lowercase()).as_bytes()),
+        }
+    }
+
+    /// Hash a virtual directory path, ignoring any trailing slash
+    pub fn hash_directory(self, path: &str) -> u64 {
+        let path = path.trim_end_matches('/');
+        match self {
+            Self::Murmur64A => murmur64a(&path.to_lowercase()),
+            Self::Fnv1a64 => fnv1a64(format!("{}++", path).as_bytes()),
+        }
+    }
+}
+
+fn murmur64a(path: &str) -> u64 {
+    let mut hasher = BuildMurmurHash64A { seed: 0x1337b33f }.build_hasher();
+    hasher.write(path.as_bytes());
+    hasher.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use murmurhash64::murmur_hash64a;
+
+    use super::{fnv1a64, PathHashAlgorithm};
+
+    #[test]
+    fn hash_paths() {
+        assert_eq!(fnv1a64(b""), 0xcbf29ce484222325);
+        assert_eq!(fnv1a64(b"a"), 0xaf63dc4c8601ec8c);
+
+        let fnv = PathHashAlgorithm::Fnv1a64;
+        assert_eq!(fnv.hash_file("Data/Mods.dat"), fnv1a64(b"data/mods.dat++"));
+        assert_eq!(fnv.hash_directory("Art/2D/"), fnv1a64(b"Art/2D++"));
+
+        let murmur = PathHashAlgorithm::Murmur64A;
+        assert_eq!(
+            murmur.hash_file("Data/Mods.dat"),
+            murmur_hash64a(b"data/mods.dat", 0x1337b33f)
+        );
+        assert_eq!(
+            murmur.hash_directory("Art/2D/"),
+            murmur_hash64a(b"art/2d", 0x1337b33f)
+        );
+    }
+}
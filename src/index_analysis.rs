@@ -0,0 +1,104 @@
+use std::collections::HashSet;
+
+use crate::{bundle_index::BundleIndex, hasher::PathHashAlgorithm, path::parse_paths};
+
+/// A file entry that none of the index's listed paths hash to
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OrphanedFile {
+    pub hash: u64,
+    pub bundle: String,
+    pub offset: u32,
+    pub size: u32,
+}
+
+/// A path rep whose hash doesn't match the directory its paths live in
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PathRepMismatch {
+    /// Position of the path rep within the index
+    pub index: usize,
+    pub hash: u64,
+    pub directory: String,
+    pub expected_hash: u64,
+}
+
+/// Find file entries that can't be reached through any of `paths`
+pub fn orphaned_files(
+    index: &BundleIndex,
+    paths: &[String],
+    hash_algorithm: PathHashAlgorithm,
+) -> Vec<OrphanedFile> {
+    let known = paths
+        .iter()
+        .map(|p| hash_algorithm.hash_file(p))
+        .collect::<HashSet<_>>();
+
+    index
+        .files
+        .iter()
+        .filter(|f| !known.contains(&f.hash))
+        .map(|f| OrphanedFile {
+            hash: f.hash,
+            bundle: index.bundles.get(f.bundle_index as usize).map_or_else(
+                || format!("<bundle {}>", f.bundle_index),
+                |b| b.name.clone(),
+            ),
+            offset: f.offset,
+            size: f.size,
+        })
+        .collect()
+}
+
+/// Check each path rep's hash against the directory of the paths it lists
+pub fn check_path_reps(
+    index: &BundleIndex,
+    hash_algorithm: PathHashAlgorithm,
+) -> Vec<PathRepMismatch> {
+    index
+        .paths
+        .iter()
+        .enumerate()
+        .filter_map(|(i, rep)| {
+            let path = parse_paths(&index.path_rep_bundle, rep)
+                .ok()?
+                .get_paths()
+                .into_iter()
+                .next()?;
+            let directory = path.rsplit_once('/').map_or("", |(d, _)| d).to_string();
+            let expected_hash = hash_algorithm.hash_directory(&directory);
+
+            (rep.hash != expected_hash).then_some(PathRepMismatch {
+                index: i,
+                hash: rep.hash,
+                directory,
+                expected_hash,
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{check_path_reps, orphaned_files};
+    use crate::{bundle_index::IndexBuilder, hasher::PathHashAlgorithm};
+
+    #[test]
+    fn find_orphans_and_mismatches() {
+        let mut builder = IndexBuilder::new();
+        let bundle = builder.add_bundle("bundle", 100);
+        builder.add_file("data/mods.datc64", bundle, 0, 10);
+        builder.add_file("data/hidden.datc64", bundle, 10, 20);
+        let mut index = builder.build().unwrap();
+
+        let paths = vec!["data/mods.datc64".to_string()];
+        let orphans = orphaned_files(&index, &paths, PathHashAlgorithm::default());
+        assert_eq!(orphans.len(), 1);
+        assert_eq!(orphans[0].bundle, "bundle");
+        assert_eq!((orphans[0].offset, orphans[0].size), (10, 20));
+
+        assert!(check_path_reps(&index, PathHashAlgorithm::default()).is_empty());
+        index.paths[0].hash ^= 1;
+        let mismatches = check_path_reps(&index, PathHashAlgorithm::default());
+        assert_eq!(mismatches.len(), 1);
+        assert_eq!(mismatches[0].directory, "data");
+    }
+}
@@ -0,0 +1,218 @@
+use std::{
+    collections::HashSet,
+    fs,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{Context, Result};
+use bytes::Bytes;
+use murmurhash64::murmur_hash64a;
+use nom::{
+    bytes::complete::{tag, take},
+    combinator::verify,
+    multi::count,
+    number::complete::le_u32,
+    IResult,
+};
+
+use crate::{
+    bundle::map_file,
+    bundle_index::{
+        parse_bundles, parse_file_infos, parse_path_reps, serialize_index_tables, BundleIndex,
+    },
+    fs_util::write_atomic,
+};
+
+/// Marks the start of a cache entry
+const MAGIC: &[u8] = b"PTIX";
+
+/// Bumped whenever the layout of cache entries changes
+const CACHE_VERSION: u32 = 1;
+
+/// An index parsed ahead of time, along with every path it contains
+pub struct CachedIndex {
+    pub index: BundleIndex,
+    pub paths: Vec<String>,
+}
+
+/// Parsed indexes stored on disk, keyed by a hash of the raw `_.index.bin`
+pub struct IndexCache {
+    folder: PathBuf,
+}
+
+impl IndexCache {
+    pub fn new(cache_dir: &Path) -> Self {
+        Self {
+            folder: cache_dir.join("index"),
+        }
+    }
+
+    fn entry_path(&self, raw_index: &[u8]) -> PathBuf {
+        let hash = murmur_hash64a(raw_index, 0x1337b33f);
+        self.folder.join(format!("{:016x}.bin", hash))
+    }
+
+    /// Look up a previously parsed index. Missing or unreadable entries count as a miss.
+    pub fn load(&self, raw_index: &[u8]) -> Option<CachedIndex> {
+        let bytes = map_file(&self.entry_path(raw_index)).ok()?;
+        parse_cached_index(&bytes).ok().map(|(_, cached)| cached)
+    }
+
+    /// Drop the entry for an index, if there is one
+    pub fn remove(&self, raw_index: &[u8]) -> Result<()> {
+        let path = self.entry_path(raw_index);
+        if path.exists() {
+            fs::remove_file(&path).with_context(|| format!("Failed to remove {:?}", path))?;
+        }
+        Ok(())
+    }
+
+    /// Entries that belong to none of `raw_indexes`, along with their sizes
+    pub fn unused_entries(&self, raw_indexes: &[Vec<u8>]) -> Result<Vec<(PathBuf, u64)>> {
+        if !self.folder.exists() {
+            return Ok(vec![]);
+        }
+
+        let used = raw_indexes
+            .iter()
+            .map(|raw_index| self.entry_path(raw_index))
+            .collect::<HashSet<_>>();
+        let mut unused = vec![];
+        for entry in fs::read_dir(&self.folder)
+            .with_context(|| format!("Failed to read {:?}", self.folder))?
+        {
+            let entry = entry?;
+            if !used.contains(&entry.path()) {
+                unused.push((entry.path(), entry.metadata()?.len()));
+            }
+        }
+        Ok(unused)
+    }
+
+    /// Save a parsed index for later runs
+    pub fn store(&self, raw_index: &[u8], index: &BundleIndex, paths: &[String]) -> Result<()> {
+        let bytes = serialize_cached_index(index, paths)?;
+
+        fs::create_dir_all(&self.folder)
+            .with_context(|| format!("Failed to create folder: {:?}", self.folder))?;
+
+        // Write to the side first so other processes never see a partial entry
+        write_atomic(&self.entry_path(raw_index), &bytes)
+    }
+}
+
+// Parser for a length prefixed UTF-8 string
+fn parse_string(input: &[u8]) -> IResult<&[u8], String> {
+    let (input, length) = le_u32(input)?;
+    let (input, data) = take(length)(input)?;
+    Ok((input, String::from_utf8_lossy(data).to_string()))
+}
+
+// Parser for a cache entry
+fn parse_cached_index(input: &[u8]) -> IResult<&[u8], CachedIndex> {
+    let (input, _) = tag(MAGIC)(input)?;
+    let (input, _) = verify(le_u32, |version| *version == CACHE_VERSION)(input)?;
+    let (input, bundles) = parse_bundles(input)?;
+    let (input, files) = parse_file_infos(input)?;
+    let (input, path_reps) = parse_path_reps(input)?;
+    let (input, path_rep_bundle_size) = le_u32(input)?;
+    let (input, path_rep_bundle) = take(path_rep_bundle_size)(input)?;
+    let (input, path_count) = le_u32(input)?;
+    let (input, paths) = count(parse_string, path_count as usize)(input)?;
+
+    Ok((
+        input,
+        CachedIndex {
+            index: BundleIndex {
+                bundles,
+                files,
+                paths: path_reps,
+                path_rep_bundle: Bytes::copy_from_slice(path_rep_bundle),
+            },
+            paths,
+        },
+    ))
+}
+
+fn serialize_cached_index(index: &BundleIndex, paths: &[String]) -> Result<Vec<u8>> {
+    let mut bytes = MAGIC.to_vec();
+    bytes.extend(CACHE_VERSION.to_le_bytes());
+
+    serialize_index_tables(index, &mut bytes)?;
+
+    bytes.extend(
+        u32::try_from(index.path_rep_bundle.len())
+            .context("Path reps too large")?
+            .to_le_bytes(),
+    );
+    bytes.extend_from_slice(&index.path_rep_bundle);
+
+    bytes.extend(
+        u32::try_from(paths.len())
+            .context("Too many paths")?
+            .to_le_bytes(),
+    );
+    for path in paths {
+        bytes.extend(
+            u32::try_from(path.len())
+                .context("Path too long")?
+                .to_le_bytes(),
+        );
+        bytes.extend(path.as_bytes());
+    }
+
+    Ok(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::IndexCache;
+    use crate::test_support::TestBundles;
+
+    #[test]
+    fn round_trip_cached_index() {
+        let folder =
+            std::env::temp_dir().join(format!("poe_tools_index_cache_{}", std::process::id()));
+
+        let index = TestBundles::new()
+            .bundle(
+                "bundle",
+                &[0; 15],
+                &[("data/mods.datc64", 0, 10), ("root.txt", 10, 5)],
+            )
+            .index();
+        let paths = vec!["data/mods.datc64".to_string(), "root.txt".to_string()];
+
+        let cache = IndexCache::new(&folder);
+        assert!(cache.load(b"raw index").is_none());
+        cache.store(b"raw index", &index, &paths).unwrap();
+
+        let cached = cache.load(b"raw index").unwrap();
+        assert_eq!(cached.paths, paths);
+        assert_eq!(cached.index.bundles[0].name, "bundle");
+        assert_eq!(cached.index.files.len(), 2);
+        assert_eq!(cached.index.files[1].hash, index.files[1].hash);
+        assert_eq!(cached.index.paths.len(), index.paths.len());
+        assert_eq!(cached.index.path_rep_bundle, index.path_rep_bundle);
+
+        // A different index file misses
+        assert!(cache.load(b"other index").is_none());
+
+        // Only entries for other indexes count as unused
+        assert!(cache
+            .unused_entries(&[b"raw index".to_vec()])
+            .unwrap()
+            .is_empty());
+        assert_eq!(
+            cache
+                .unused_entries(&[b"other index".to_vec()])
+                .unwrap()
+                .len(),
+            1
+        );
+
+        fs::remove_dir_all(folder).unwrap();
+    }
+}
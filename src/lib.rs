@@ -1,9 +1,24 @@
+pub mod batch_read;
 pub mod bundle;
+pub mod bundle_cache;
 pub mod bundle_fs;
 pub mod bundle_index;
 pub mod bundle_loader;
+pub mod bundle_source;
+pub mod cdn_cache;
 pub mod commands;
 pub mod dat;
+pub mod file_system;
+mod fs_util;
+pub mod ggpk;
 pub mod hasher;
+pub mod index_analysis;
+pub mod index_cache;
+pub mod overlay_fs;
+pub mod parse_error;
 pub mod path;
+pub mod path_tree;
 pub mod steam;
+#[cfg(test)]
+mod test_support;
+pub mod virtual_file;
@@ -0,0 +1,208 @@
+use std::{
+    collections::HashMap,
+    fs,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{Context, Result};
+use bytes::Bytes;
+
+use crate::file_system::{BatchReadItem, FileSystem};
+
+/// Recursively list the files in a folder, keyed by their path relative to it using `/`
+/// separators
+pub fn collect_local_files(root: &Path) -> Result<Vec<(String, PathBuf)>> {
+    fn collect(root: &Path, dir: &Path, files: &mut Vec<(String, PathBuf)>) -> Result<()> {
+        for entry in
+            fs::read_dir(dir).with_context(|| format!("Failed to read folder: {:?}", dir))?
+        {
+            let path = entry.context("Failed to read folder entry")?.path();
+            if path.is_dir() {
+                collect(root, &path, files)?;
+            } else {
+                let virtual_path = path
+                    .strip_prefix(root)?
+                    .components()
+                    .map(|c| c.as_os_str().to_string_lossy())
+                    .collect::<Vec<_>>()
+                    .join("/");
+                files.push((virtual_path, path));
+            }
+        }
+
+        Ok(())
+    }
+
+    let mut files = vec![];
+    collect(root, root, &mut files)?;
+    files.sort();
+    Ok(files)
+}
+
+/// Layers local folders over another file system. Any file in a folder shadows the wrapped
+/// version of it, with later folders taking priority over earlier ones.
+pub struct OverlayFS {
+    base: Box<dyn FileSystem>,
+    layers: Vec<PathBuf>,
+    /// Lowercased virtual path -> (virtual path, layer index, path on disk)
+    files: HashMap<String, (String, usize, PathBuf)>,
+}
+
+impl OverlayFS {
+    pub fn new(base: Box<dyn FileSystem>, layers: Vec<PathBuf>) -> Result<Self> {
+        let mut files = HashMap::new();
+        for (layer_index, layer) in layers.iter().enumerate() {
+            let layer_files = collect_local_files(layer)
+                .with_context(|| format!("Failed to scan overlay folder: {:?}", layer))?;
+            for (virtual_path, path) in layer_files {
+                files.insert(
+                    virtual_path.to_lowercase(),
+                    (virtual_path, layer_index, path),
+                );
+            }
+        }
+
+        Ok(Self {
+            base,
+            layers,
+            files,
+        })
+    }
+
+    /// The folder a file is read from, or `None` if it comes from the wrapped file system
+    pub fn layer(&self, path: &str) -> Option<&Path> {
+        self.files
+            .get(&path.to_lowercase())
+            .map(|(_, layer_index, _)| self.layers[*layer_index].as_path())
+    }
+
+    fn read_local(&self, path: &str) -> Option<Result<Bytes>> {
+        self.files
+            .get(&path.to_lowercase())
+            .map(|(_, _, local_path)| {
+                fs::read(local_path)
+                    .map(Bytes::from)
+                    .with_context(|| format!("Failed to read overlay file: {:?}", local_path))
+            })
+    }
+}
+
+impl FileSystem for OverlayFS {
+    fn list(&self) -> Box<dyn Iterator<Item = String> + '_> {
+        let base = self
+            .base
+            .list()
+            .filter(|path| !self.files.contains_key(&path.to_lowercase()));
+        let overlaid = self.files.values().map(|(path, _, _)| path.clone());
+
+        Box::new(base.chain(overlaid))
+    }
+
+    fn read(&self, path: &str) -> Result<Bytes> {
+        match self.read_local(path) {
+            Some(contents) => contents,
+            None => self.base.read(path),
+        }
+    }
+
+    fn batch_read<'a>(
+        &'a self,
+        paths: &[&'a str],
+    ) -> Box<dyn Iterator<Item = BatchReadItem<'a>> + 'a> {
+        let (overlaid, base): (Vec<&'a str>, Vec<&'a str>) = paths
+            .iter()
+            .copied()
+            .partition(|path| self.files.contains_key(&path.to_lowercase()));
+
+        let overlaid = overlaid.into_iter().map(move |path| {
+            self.read(path)
+                .map(|contents| (path, contents))
+                .map_err(|e| (path, e))
+        });
+
+        Box::new(overlaid.chain(self.base.batch_read(&base)))
+    }
+
+    fn origin(&self, path: &str) -> Option<String> {
+        Some(
+            self.layer(path)
+                .map(|layer| layer.display().to_string())
+                .unwrap_or_else(|| "base".to_string()),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::HashMap, fs};
+
+    use anyhow::{Context, Result};
+    use bytes::Bytes;
+
+    use super::OverlayFS;
+    use crate::file_system::{BatchReadItem, FileSystem};
+
+    struct MemoryFS(HashMap<String, Bytes>);
+
+    impl FileSystem for MemoryFS {
+        fn list(&self) -> Box<dyn Iterator<Item = String> + '_> {
+            Box::new(self.0.keys().cloned())
+        }
+
+        fn read(&self, path: &str) -> Result<Bytes> {
+            self.0.get(path).cloned().context("Path not found")
+        }
+
+        fn batch_read<'a>(
+            &'a self,
+            paths: &[&'a str],
+        ) -> Box<dyn Iterator<Item = BatchReadItem<'a>> + 'a> {
+            Box::new(paths.to_vec().into_iter().map(move |path| {
+                self.read(path)
+                    .map(|contents| (path, contents))
+                    .map_err(|e| (path, e))
+            }))
+        }
+    }
+
+    #[test]
+    fn overlay_shadows_base() {
+        let layer = std::env::temp_dir().join(format!("poe_tools_overlay_{}", std::process::id()));
+        fs::create_dir_all(layer.join("Data")).unwrap();
+        fs::write(layer.join("Data/A.txt"), "modded").unwrap();
+        fs::write(layer.join("new.txt"), "new").unwrap();
+
+        let base = MemoryFS(HashMap::from([
+            ("data/a.txt".to_string(), Bytes::from("original")),
+            ("data/b.txt".to_string(), Bytes::from("untouched")),
+        ]));
+        let overlay = OverlayFS::new(Box::new(base), vec![layer.clone()]).unwrap();
+
+        let mut paths = overlay.list().collect::<Vec<_>>();
+        paths.sort();
+        assert_eq!(paths, ["Data/A.txt", "data/b.txt", "new.txt"]);
+
+        assert_eq!(overlay.read("data/a.txt").unwrap(), "modded");
+        assert_eq!(overlay.read("data/b.txt").unwrap(), "untouched");
+        assert_eq!(overlay.origin("data/b.txt").unwrap(), "base");
+        assert_eq!(
+            overlay.origin("new.txt").unwrap(),
+            layer.display().to_string()
+        );
+
+        let mut results = overlay
+            .batch_read(&["new.txt", "data/b.txt"])
+            .map(|r| r.unwrap())
+            .collect::<Vec<_>>();
+        results.sort();
+        assert_eq!(
+            results,
+            [
+                ("data/b.txt", Bytes::from("untouched")),
+                ("new.txt", Bytes::from("new"))
+            ]
+        );
+
+        fs::remove_dir_all(layer).unwrap();
+    }
+}
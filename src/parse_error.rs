@@ -0,0 +1,75 @@
+use std::fmt;
+
+/// A binary parser failed partway through its input
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseError {
+    /// What was being parsed
+    pub context: &'static str,
+    /// Byte offset into the input where parsing failed
+    pub offset: usize,
+    /// Why parsing failed
+    pub reason: String,
+}
+
+impl ParseError {
+    pub fn new(context: &'static str, offset: usize, reason: impl Into<String>) -> Self {
+        Self {
+            context,
+            offset,
+            reason: reason.into(),
+        }
+    }
+
+    /// Convert a nom error, working out its offset from where it occurred within `input`
+    pub fn from_nom(
+        context: &'static str,
+        input: &[u8],
+        error: nom::Err<nom::error::Error<&[u8]>>,
+    ) -> Self {
+        match error {
+            nom::Err::Incomplete(_) => Self::new(context, input.len(), "Unexpected end of input"),
+            nom::Err::Error(e) | nom::Err::Failure(e) => {
+                Self::new(context, offset_within(input, e.input), e.code.description())
+            }
+        }
+    }
+}
+
+/// Position of `remaining` within `input`, clamped to the input for slices from elsewhere
+fn offset_within(input: &[u8], remaining: &[u8]) -> usize {
+    (remaining.as_ptr() as usize)
+        .saturating_sub(input.as_ptr() as usize)
+        .min(input.len())
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Failed to parse {} at byte {}: {}",
+            self.context, self.offset, self.reason
+        )
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+#[cfg(test)]
+mod tests {
+    use nom::number::complete::le_u32;
+
+    use super::ParseError;
+
+    #[test]
+    fn offset_from_nom_error() {
+        let input = [1, 0, 0, 0, 2, 0];
+        let error = le_u32::<_, nom::error::Error<&[u8]>>(&input[4..]).unwrap_err();
+
+        let error = ParseError::from_nom("test", &input, error);
+        assert_eq!(error.offset, 4);
+        assert_eq!(
+            error.to_string(),
+            "Failed to parse test at byte 4: End of file"
+        );
+    }
+}
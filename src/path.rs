@@ -1,72 +1,114 @@
-use crate::bundle_index::PathRep;
+use nom::{
+    bytes::complete::{tag, take, take_till},
+    combinator::{map_res, opt},
+    number::complete::le_u32,
+    IResult,
+};
+
+use crate::{bundle_index::PathRep, parse_error::ParseError};
+
+// Parser for a path segment - the index of its parent plus one, then a null-terminated string
+fn parse_segment(input: &[u8]) -> IResult<&[u8], (u32, String)> {
+    let (input, idx) = le_u32(input)?;
+    let (input, value) = map_res(take_till(|b| b == 0), |bytes: &[u8]| {
+        String::from_utf8(bytes.to_vec())
+    })(input)?;
+    let (input, _) = opt(tag(&[0u8][..]))(input)?;
+    Ok((input, (idx, value)))
+}
 
-// Pull out the path components from the bundle blob into a more usable structure
-pub fn parse_paths(path_rep_bytes: &[u8], path_rep: &PathRep) -> ParsedPathRep {
-    let full_bytes = &path_rep_bytes
-        [path_rep.offset as usize..path_rep.offset as usize + path_rep.size as usize];
+// Parser for the segments of a path rep chunk
+fn parse_segments(input: &[u8]) -> IResult<&[u8], ParsedPathRep> {
+    let (mut input, _) = take(4usize)(input)?;
 
-    let mut i = 4;
+    // Segments can only refer back to base paths that came before them
+    let parent = |idx: u32, bases: &[PathSegment]| {
+        (idx as usize).checked_sub(1).filter(|&p| p < bases.len())
+    };
 
     // Base paths
     let mut bases = vec![];
     loop {
-        let idx = u32::from_le_bytes(full_bytes[i..i + 4].try_into().unwrap()) as usize;
-
+        let (rest, idx) = le_u32(input)?;
         if idx == 0 {
-            i += 4;
+            input = rest;
             break;
         }
 
-        let path = String::from_utf8(
-            full_bytes[i + 4..]
-                .iter()
-                .take_while(|&&b| b != b'\0')
-                .copied()
-                .collect(),
-        )
-        .expect("Failed to parse string.");
-
-        i += 4 + path.len() + 1;
-
+        let (rest, (idx, value)) = parse_segment(input)?;
+        input = rest;
         bases.push(PathSegment {
-            value: path,
+            value,
             is_leaf: false,
-            parent_index: if idx - 1 < bases.len() {
-                Some(idx - 1)
-            } else {
-                None
-            },
+            parent_index: parent(idx, &bases),
         });
     }
 
     // Leaf paths
     let mut leaves = vec![];
-    while i < full_bytes.len() {
-        let idx = u32::from_le_bytes(full_bytes[i..i + 4].try_into().unwrap()) as usize;
-
-        let path = String::from_utf8(
-            full_bytes[i + 4..]
-                .iter()
-                .take_while(|&&b| b != b'\0')
-                .copied()
-                .collect(),
-        )
-        .expect("Failed to parse string.");
-
-        i += 4 + path.len() + 1;
-
+    while !input.is_empty() {
+        let (rest, (idx, value)) = parse_segment(input)?;
+        input = rest;
         leaves.push(PathSegment {
-            value: path,
+            value,
             is_leaf: true,
-            parent_index: if idx - 1 < bases.len() {
-                Some(idx - 1)
-            } else {
-                None
-            },
+            parent_index: parent(idx, &bases),
         });
     }
 
-    ParsedPathRep { bases, leaves }
+    Ok((input, ParsedPathRep { bases, leaves }))
+}
+
+// Pull out the path components from the bundle blob into a more usable structure. Error offsets
+// are relative to the start of the path rep bundle.
+pub fn parse_paths(path_rep_bytes: &[u8], path_rep: &PathRep) -> Result<ParsedPathRep, ParseError> {
+    let start = path_rep.offset as usize;
+    let full_bytes = start
+        .checked_add(path_rep.size as usize)
+        .and_then(|end| path_rep_bytes.get(start..end))
+        .ok_or_else(|| {
+            ParseError::new(
+                "path rep",
+                start,
+                format!(
+                    "{} bytes at offset {} lie outside of the path rep bundle",
+                    path_rep.size, start
+                ),
+            )
+        })?;
+
+    parse_segments(full_bytes)
+        .map(|(_, parsed)| parsed)
+        .map_err(|e| {
+            let error = ParseError::from_nom("path rep", full_bytes, e);
+            ParseError {
+                offset: start + error.offset,
+                ..error
+            }
+        })
+}
+
+/// Encode the files of a single directory into a path-rep chunk that `parse_paths` can read
+pub fn encode_paths(directory: &str, leaves: &[&str]) -> Vec<u8> {
+    let mut bytes = vec![];
+
+    // Base paths - the directory is the only one, and has no parent
+    bytes.extend(0u32.to_le_bytes());
+    if !directory.is_empty() {
+        bytes.extend(1u32.to_le_bytes());
+        bytes.extend(directory.as_bytes());
+        bytes.extend(b"/\0");
+    }
+    bytes.extend(0u32.to_le_bytes());
+
+    // Leaf paths, all pointing at the directory
+    for leaf in leaves {
+        bytes.extend(1u32.to_le_bytes());
+        bytes.extend(leaf.as_bytes());
+        bytes.push(0);
+    }
+
+    bytes
 }
 
 pub struct ParsedPathRep {
@@ -102,3 +144,43 @@ pub struct PathSegment {
     pub is_leaf: bool,
     pub parent_index: Option<usize>,
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{encode_paths, parse_paths};
+    use crate::bundle_index::PathRep;
+
+    fn path_rep(offset: u32, size: u32) -> PathRep {
+        PathRep {
+            hash: 0,
+            offset,
+            size,
+            recursive_size: size,
+        }
+    }
+
+    #[test]
+    fn reject_malformed_path_reps() {
+        let bytes = encode_paths("data", &["mods.datc64"]);
+        let size = bytes.len() as u32;
+        let paths = parse_paths(&bytes, &path_rep(0, size)).unwrap().get_paths();
+        assert_eq!(paths, ["data/mods.datc64"]);
+
+        // Outside of the path rep bundle
+        let error = parse_paths(&bytes, &path_rep(4, size)).err().unwrap();
+        assert_eq!(error.offset, 4);
+
+        // Truncated partway through the base paths
+        let error = parse_paths(&bytes, &path_rep(0, 6)).err().unwrap();
+        assert_eq!(error.offset, 4);
+
+        // Leaves pointing at a parent that doesn't exist are kept as-is
+        let mut bytes = bytes;
+        bytes.extend(0u32.to_le_bytes());
+        bytes.extend(b"root.txt\0");
+        let paths = parse_paths(&bytes, &path_rep(0, bytes.len() as u32))
+            .unwrap()
+            .get_paths();
+        assert_eq!(paths, ["data/mods.datc64", "root.txt"]);
+    }
+}
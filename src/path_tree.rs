@@ -0,0 +1,185 @@
+use std::collections::{btree_map::Entry, BTreeMap, HashMap};
+
+use crate::{bundle_index::BundleIndex, hasher::PathHashAlgorithm};
+
+/// A file or directory within a directory
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DirEntry {
+    pub name: String,
+    pub is_dir: bool,
+    /// File size, or the total size of the files within a directory. Unknown for files missing
+    /// from the index.
+    pub size: Option<u64>,
+}
+
+#[derive(Default)]
+struct Directory {
+    /// Lowercased name -> entry
+    children: BTreeMap<String, DirEntry>,
+}
+
+/// Directory tree built from the path reps of an index
+pub struct PathTree {
+    /// Lowercased directory path, without a trailing slash -> contents
+    directories: HashMap<String, Directory>,
+}
+
+/// Lowercase a directory path and strip surrounding slashes
+fn directory_key(path: &str) -> String {
+    path.trim_matches('/').to_lowercase()
+}
+
+impl PathTree {
+    /// Build the tree from the paths listed by an index, looking up file sizes through `lut`
+    pub fn build(
+        index: &BundleIndex,
+        paths: &[String],
+        lut: &HashMap<u64, usize>,
+        hash_algorithm: PathHashAlgorithm,
+    ) -> PathTree {
+        let mut directories = HashMap::<String, Directory>::new();
+        directories.insert(String::new(), Directory::default());
+        // Lowercased directory path -> total size of the files beneath it
+        let mut directory_sizes = HashMap::<String, u64>::new();
+
+        for path in paths {
+            let components = path
+                .split('/')
+                .filter(|c| !c.is_empty())
+                .collect::<Vec<_>>();
+            let Some((&file_name, directory_names)) = components.split_last() else {
+                continue;
+            };
+
+            let mut parent = String::new();
+            for &name in directory_names {
+                let child = if parent.is_empty() {
+                    name.to_string()
+                } else {
+                    format!("{}/{}", parent, name)
+                };
+                directories
+                    .entry(parent.to_lowercase())
+                    .or_default()
+                    .children
+                    .entry(name.to_lowercase())
+                    .or_insert_with(|| DirEntry {
+                        name: name.to_string(),
+                        is_dir: true,
+                        size: None,
+                    });
+                directories.entry(child.to_lowercase()).or_default();
+                parent = child;
+            }
+
+            let children = &mut directories
+                .entry(parent.to_lowercase())
+                .or_default()
+                .children;
+            // Paths listed twice only count once towards their directories
+            let Entry::Vacant(entry) = children.entry(file_name.to_lowercase()) else {
+                continue;
+            };
+            let path = components.join("/");
+            let size = lut
+                .get(&hash_algorithm.hash_file(&path))
+                .map(|&i| index.files[i].size as u64);
+            entry.insert(DirEntry {
+                name: file_name.to_string(),
+                is_dir: false,
+                size,
+            });
+
+            for depth in 1..=directory_names.len() {
+                *directory_sizes
+                    .entry(directory_names[..depth].join("/").to_lowercase())
+                    .or_default() += size.unwrap_or(0);
+            }
+        }
+
+        // Directories report the total size of the files beneath them
+        for (key, directory) in &mut directories {
+            for (name, entry) in &mut directory.children {
+                if entry.is_dir {
+                    let child = if key.is_empty() {
+                        name.clone()
+                    } else {
+                        format!("{}/{}", key, name)
+                    };
+                    entry.size = directory_sizes.get(&child).copied();
+                }
+            }
+        }
+
+        PathTree { directories }
+    }
+
+    /// Whether a directory exists. The root is the empty string.
+    pub fn is_dir(&self, path: &str) -> bool {
+        self.directories.contains_key(&directory_key(path))
+    }
+
+    /// List the contents of a directory, sorted by name
+    pub fn read_dir(&self, path: &str) -> Option<Vec<DirEntry>> {
+        self.directories
+            .get(&directory_key(path))
+            .map(|d| d.children.values().cloned().collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::{DirEntry, PathTree};
+    use crate::{bundle_index::IndexBuilder, hasher::PathHashAlgorithm, path::parse_paths};
+
+    #[test]
+    fn build_tree() {
+        let mut builder = IndexBuilder::new();
+        let bundle = builder.add_bundle("bundle", 100);
+        builder.add_file("Data/Mods.datc64", bundle, 0, 10);
+        builder.add_file("data/stats.datc64", bundle, 10, 20);
+        builder.add_file("art/2d/a.dds", bundle, 30, 5);
+        builder.add_file("root.txt", bundle, 35, 1);
+        let index = builder.build().unwrap();
+        let lut = index
+            .files
+            .iter()
+            .enumerate()
+            .map(|(i, f)| (f.hash, i))
+            .collect::<HashMap<_, _>>();
+
+        let paths = index
+            .paths
+            .iter()
+            .flat_map(|p| parse_paths(&index.path_rep_bundle, p).unwrap().get_paths())
+            .collect::<Vec<_>>();
+
+        let tree = PathTree::build(&index, &paths, &lut, PathHashAlgorithm::default());
+
+        let root = tree.read_dir("").unwrap();
+        let names = root.iter().map(|e| e.name.as_str()).collect::<Vec<_>>();
+        assert_eq!(names, ["art", "Data", "root.txt"]);
+        assert_eq!(
+            root[2],
+            DirEntry {
+                name: "root.txt".to_string(),
+                is_dir: false,
+                size: Some(1),
+            }
+        );
+
+        assert!(tree.is_dir("art/2d/"));
+        assert!(!tree.is_dir("root.txt"));
+        assert_eq!(tree.read_dir("DATA").unwrap().len(), 2);
+        assert!(tree.read_dir("missing").is_none());
+
+        // Directories report the total size of the files beneath them
+        assert_eq!(root[0].size, Some(5));
+        assert_eq!(root[1].size, Some(30));
+        let art = tree.read_dir("art").unwrap();
+        assert!(art[0].is_dir);
+        assert_eq!(art[0].size, Some(5));
+    }
+}
@@ -0,0 +1,136 @@
+use std::{collections::HashMap, fs, path::Path};
+
+use anyhow::{Context, Result};
+use bytes::Bytes;
+
+use crate::{
+    bundle::{serialize_bundle, StoredEncoder},
+    bundle_fs::FS,
+    bundle_index::{serialize_bundle_index, BundleIndex, BundleInfo, IndexBuilder},
+    bundle_source::BundleSource,
+};
+
+/// Block size of test bundles, small so that files span several blocks
+pub(crate) const TEST_GRANULARITY: u32 = 4;
+
+/// A small set of bundles and the index describing them, written with the bundle and index
+/// serializers
+#[derive(Default)]
+pub(crate) struct TestBundles {
+    bundles: Vec<(String, u32, Option<Bytes>)>,
+    files: Vec<(String, u32, u32, u32)>,
+}
+
+impl TestBundles {
+    pub(crate) fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add a bundle holding `content`, with its files given as `(path, offset, size)`
+    pub(crate) fn bundle(self, name: &str, content: &[u8], files: &[(&str, u32, u32)]) -> Self {
+        self.bundle_sized(name, content.len() as u32, content, files)
+    }
+
+    /// Like `bundle`, but the index claims `uncompressed_size` whatever the content's real size
+    pub(crate) fn bundle_sized(
+        mut self,
+        name: &str,
+        uncompressed_size: u32,
+        content: &[u8],
+        files: &[(&str, u32, u32)],
+    ) -> Self {
+        let bytes = serialize_bundle(content, TEST_GRANULARITY, &StoredEncoder).unwrap();
+        let bundle_index = self.bundles.len() as u32;
+        self.bundles.push((
+            name.to_string(),
+            uncompressed_size,
+            Some(Bytes::from(bytes)),
+        ));
+        self.files.extend(
+            files
+                .iter()
+                .map(|&(path, offset, size)| (path.to_string(), bundle_index, offset, size)),
+        );
+        self
+    }
+
+    /// Add a bundle to the index without a bundle file behind it
+    pub(crate) fn missing_bundle(mut self, name: &str, uncompressed_size: u32) -> Self {
+        self.bundles
+            .push((name.to_string(), uncompressed_size, None));
+        self
+    }
+
+    /// The parsed index
+    pub(crate) fn index(&self) -> BundleIndex {
+        let mut builder = IndexBuilder::new();
+        for (name, uncompressed_size, _) in &self.bundles {
+            builder.add_bundle(name, *uncompressed_size);
+        }
+        for (path, bundle_index, offset, size) in &self.files {
+            builder.add_file(path, *bundle_index, *offset, *size);
+        }
+        builder.build().unwrap()
+    }
+
+    /// The raw `_.index.bin`
+    pub(crate) fn index_bytes(&self) -> Bytes {
+        Bytes::from(serialize_bundle_index(&self.index(), &StoredEncoder).unwrap())
+    }
+
+    /// The raw contents of a bundle file
+    pub(crate) fn bundle_bytes(&self, name: &str) -> Bytes {
+        self.bundles
+            .iter()
+            .find(|(bundle, _, _)| bundle == name)
+            .and_then(|(_, _, bytes)| bytes.clone())
+            .unwrap()
+    }
+
+    /// Write the index and bundles to `folder`, laid out like `Bundles2/`
+    pub(crate) fn write_to(&self, folder: &Path) {
+        fs::create_dir_all(folder).unwrap();
+        fs::write(folder.join("_.index.bin"), self.index_bytes()).unwrap();
+        for (name, _, bytes) in &self.bundles {
+            if let Some(bytes) = bytes {
+                fs::write(folder.join(format!("{}.bundle.bin", name)), bytes).unwrap();
+            }
+        }
+    }
+
+    /// Serve the index and bundles from memory
+    pub(crate) fn source(&self) -> MemorySource {
+        MemorySource {
+            index: self.index_bytes(),
+            bundles: self
+                .bundles
+                .iter()
+                .filter_map(|(name, _, bytes)| Some((name.clone(), bytes.clone()?)))
+                .collect(),
+        }
+    }
+
+    /// A file system over the bundles, served from memory
+    pub(crate) fn fs(&self) -> FS {
+        FS::from_source(self.source()).unwrap()
+    }
+}
+
+/// Serves bundles from memory
+pub(crate) struct MemorySource {
+    index: Bytes,
+    bundles: HashMap<String, Bytes>,
+}
+
+impl BundleSource for MemorySource {
+    fn load_index(&self) -> Result<Bytes> {
+        Ok(self.index.clone())
+    }
+
+    fn load_bundle(&self, bundle: &BundleInfo) -> Result<Bytes> {
+        self.bundles
+            .get(&bundle.name)
+            .cloned()
+            .context("No such bundle")
+    }
+}
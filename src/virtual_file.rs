@@ -0,0 +1,139 @@
+use std::{
+    io::{self, Read, Seek, SeekFrom},
+    sync::Arc,
+};
+
+use bytes::Bytes;
+
+use crate::{bundle::Bundle, bundle_fs::FS};
+
+/// Streaming handle to a file within a bundle. Only the blocks covering the current position are
+/// decompressed, through the file system's block cache.
+pub struct VirtualFile<'a> {
+    fs: &'a FS,
+    bundle_index: u32,
+    bundle: Arc<Bundle>,
+    /// Start of the file within the bundle's content
+    offset: u64,
+    size: u64,
+    pos: u64,
+    /// Most recently used block, and its index
+    current: Option<(usize, Bytes)>,
+}
+
+impl<'a> VirtualFile<'a> {
+    pub(crate) fn new(
+        fs: &'a FS,
+        bundle_index: u32,
+        bundle: Arc<Bundle>,
+        offset: u64,
+        size: u64,
+    ) -> Self {
+        Self {
+            fs,
+            bundle_index,
+            bundle,
+            offset,
+            size,
+            pos: 0,
+            current: None,
+        }
+    }
+
+    /// Size of the file in bytes
+    pub fn size(&self) -> u64 {
+        self.size
+    }
+
+    fn block(&mut self, block_index: usize) -> io::Result<Bytes> {
+        if let Some((index, block)) = &self.current {
+            if *index == block_index {
+                return Ok(block.clone());
+            }
+        }
+
+        let block = self
+            .fs
+            .block(self.bundle_index, &self.bundle, block_index)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        self.current = Some((block_index, block.clone()));
+        Ok(block)
+    }
+}
+
+impl Read for VirtualFile<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.pos >= self.size || buf.is_empty() {
+            return Ok(0);
+        }
+
+        let block_size = self.bundle.head.uncompressed_block_granularity as u64;
+        let position = self.offset + self.pos;
+        let block = self.block((position / block_size) as usize)?;
+
+        let start = (position % block_size) as usize;
+        let available = (block.len().saturating_sub(start) as u64).min(self.size - self.pos);
+        if available == 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "Decoded block is shorter than expected",
+            ));
+        }
+
+        let len = (available as usize).min(buf.len());
+        buf[..len].copy_from_slice(&block[start..start + len]);
+        self.pos += len as u64;
+        Ok(len)
+    }
+}
+
+impl Seek for VirtualFile<'_> {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let pos = match pos {
+            SeekFrom::Start(offset) => Some(offset),
+            SeekFrom::End(offset) => self.size.checked_add_signed(offset),
+            SeekFrom::Current(offset) => self.pos.checked_add_signed(offset),
+        }
+        .ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "Invalid seek to a negative or overflowing position",
+            )
+        })?;
+
+        self.pos = pos;
+        Ok(pos)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{Read, Seek, SeekFrom};
+
+    use crate::test_support::TestBundles;
+
+    #[test]
+    fn stream_across_blocks() {
+        let file_system = TestBundles::new()
+            .bundle("bundle", b"0123456789abcdefghij", &[("file.txt", 3, 15)])
+            .fs();
+        let mut file = file_system.open("file.txt").unwrap();
+        assert_eq!(file.size(), 15);
+
+        // Small reads stop at block boundaries
+        let mut buf = [0; 8];
+        assert_eq!(file.read(&mut buf).unwrap(), 1);
+        assert_eq!(&buf[..1], b"3");
+
+        file.seek(SeekFrom::Start(5)).unwrap();
+        let mut rest = vec![];
+        file.read_to_end(&mut rest).unwrap();
+        assert_eq!(rest, b"89abcdefgh");
+
+        file.seek(SeekFrom::End(-2)).unwrap();
+        let mut tail = String::new();
+        file.read_to_string(&mut tail).unwrap();
+        assert_eq!(tail, "gh");
+        assert!(file.seek(SeekFrom::Current(-100)).is_err());
+    }
+}